@@ -0,0 +1,148 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::schema::*;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// Every component a `Workers` registry can run, for the editor to build its
+/// palette from
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Catalog {
+    pub components: Vec<ComponentSchema>,
+}
+
+impl Catalog {
+    pub fn get(&self, name: &str) -> Option<&ComponentSchema> {
+        self.components.iter().find(|c| c.name == name)
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// TypeScript definitions for the `node.data` of each component, plus the
+    /// catalog itself as a constant
+    pub fn to_typescript(&self) -> String {
+        let mut ts = String::new();
+        ts.push_str("// Generated from the d3ne worker registry, do not edit.\n\n");
+        ts.push_str(
+            "export type SocketType = \"any\" | \"action\" | \"string\" | \"bytes\" | \"i64\" | \"u64\";\n",
+        );
+        for component in &self.components {
+            let _ = writeln!(ts);
+            if let Some(description) = &component.description {
+                let _ = writeln!(ts, "/** {} */", description);
+            }
+            let _ = writeln!(ts, "export interface {}Data {{", type_name(&component.name));
+            for control in &component.controls {
+                if let Some(description) = &control.description {
+                    let _ = writeln!(ts, "  /** {} */", description);
+                }
+                let optional = !control.required || control.default.is_some();
+                let _ = writeln!(
+                    ts,
+                    "  {}{}: {};",
+                    property_name(&control.name),
+                    if optional { "?" } else { "" },
+                    control_type(control.kind)
+                );
+            }
+            ts.push_str("}\n");
+        }
+        ts.push_str("\nexport interface ComponentData {\n");
+        for component in &self.components {
+            let _ = writeln!(
+                ts,
+                "  {}: {}Data;",
+                property_name(&component.name),
+                type_name(&component.name)
+            );
+        }
+        ts.push_str("}\n\nexport type ComponentName = keyof ComponentData;\n");
+        let json = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        let _ = writeln!(ts, "\nexport const catalog = {} as const;", json);
+        ts
+    }
+}
+
+fn control_type(kind: ControlType) -> &'static str {
+    match kind {
+        ControlType::Any => "unknown",
+        ControlType::String => "string",
+        ControlType::Number | ControlType::Integer => "number",
+        ControlType::Boolean => "boolean",
+        ControlType::Object => "Record<string, unknown>",
+        ControlType::Array => "unknown[]",
+    }
+}
+
+/// `"my node"` becomes `MyNode`
+fn type_name(name: &str) -> String {
+    let name: String = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|c| c.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) || name.is_empty() {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+fn property_name(name: &str) -> String {
+    let plain = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if plain {
+        name.to_string()
+    } else {
+        serde_json::to_string(name).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_typescript() {
+        let catalog = Catalog {
+            components: vec![ComponentSchema::new("big number")
+                .description("A number")
+                .output(PortSchema::new("num", SocketType::I64))
+                .control(ControlSchema::new("num", ControlType::Integer))
+                .control(ControlSchema::new("my label", ControlType::String).optional())
+                .control(ControlSchema::new("scale", ControlType::Number).default_value(json!(1)))],
+        };
+        let ts = catalog.to_typescript();
+        assert!(ts.contains(
+            "/** A number */\nexport interface BigNumberData {\n  num: number;\n  \"my label\"?: string;\n  scale?: number;\n}\n"
+        ));
+        assert!(ts.contains("  \"big number\": BigNumberData;\n"));
+        assert!(ts.contains("export const catalog = {"));
+
+        let json: Catalog = serde_json::from_str(&catalog.to_json().unwrap()).unwrap();
+        assert_eq!(json, catalog);
+    }
+}
@@ -0,0 +1,139 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::engine::{EngineError, NodeOutputs};
+use crate::execution::{Execution, Step};
+use crate::invocation::RunState;
+use crate::node::*;
+use std::collections::HashMap;
+
+/// Where a `Debugger` pauses
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Breakpoint {
+    Node(NodeId),
+    /// Any node run by the worker with this name
+    Worker(String),
+}
+
+impl Breakpoint {
+    pub fn matches(&self, node: &Node) -> bool {
+        match self {
+            Breakpoint::Node(id) => node.id == *id,
+            Breakpoint::Worker(name) => node.name == *name,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DebugEvent {
+    /// Paused before the worker of this node runs
+    Paused(NodeId),
+    /// The run completed with the outputs of the node it ended at
+    Finished(HashMap<String, OutputValue>),
+}
+
+/// A run that stops before the worker of every node matching a breakpoint.
+/// While paused the inputs gathered for that node and the outputs of the nodes
+/// that already ran can be inspected.
+pub struct Debugger<'a, TContext> {
+    execution: Execution<'a, TContext>,
+    breakpoints: Vec<Breakpoint>,
+}
+
+impl<'a, TContext> Debugger<'a, TContext> {
+    pub(crate) fn new(execution: Execution<'a, TContext>) -> Self {
+        Self {
+            execution,
+            breakpoints: vec![],
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> &mut Self {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+        self
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) -> &mut Self {
+        self.breakpoints.retain(|b| b != breakpoint);
+        self
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Runs the paused node, if any, then continues until the next breakpoint
+    /// or the end of the run
+    pub fn resume(&mut self) -> Result<DebugEvent, EngineError> {
+        self.execution.run_pending()?;
+        loop {
+            match self.execution.advance()? {
+                Step::Pending(id) => {
+                    let node = &self.execution.nodes()[&id];
+                    if self.breakpoints.iter().any(|b| b.matches(node)) {
+                        return Ok(DebugEvent::Paused(id));
+                    }
+                    self.execution.run_pending()?;
+                }
+                Step::Finished(end_id) => {
+                    return Ok(DebugEvent::Finished(self.execution.outputs(end_id)))
+                }
+            }
+        }
+    }
+
+    /// Runs the paused node, if any, and pauses before the next one
+    pub fn step(&mut self) -> Result<DebugEvent, EngineError> {
+        self.execution.run_pending()?;
+        Ok(match self.execution.advance()? {
+            Step::Pending(id) => DebugEvent::Paused(id),
+            Step::Finished(end_id) => DebugEvent::Finished(self.execution.outputs(end_id)),
+        })
+    }
+
+    /// The node the debugger is paused at
+    pub fn pending_node(&self) -> Option<&Node> {
+        self.execution.pending().map(|(node, _)| node)
+    }
+
+    /// The inputs gathered for the node the debugger is paused at
+    pub fn pending_inputs(&self) -> Option<&HashMap<String, OutputValue>> {
+        self.execution.pending().map(|(_, input_data)| input_data)
+    }
+
+    /// Outputs of `id` if it already ran
+    pub fn outputs(&self, id: NodeId) -> Option<&HashMap<String, OutputValue>> {
+        self.execution.cache().get(&id).map(|o| o.as_ref())
+    }
+
+    /// What the workers stored and emitted so far
+    pub fn state(&self) -> &RunState {
+        self.execution.state()
+    }
+
+    /// Ids of the nodes that already ran
+    pub fn completed(&self) -> Vec<NodeId> {
+        let mut ids: Vec<NodeId> = self.execution.cache().keys().copied().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Stops the run and returns the outputs computed so far. Observers see the
+    /// run fail with `EngineError::Aborted`.
+    pub fn abort(mut self) -> NodeOutputs {
+        self.execution.abort();
+        self.execution.into_outputs()
+    }
+}
@@ -11,190 +11,787 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+use crate::execution::Execution;
+use crate::invocation::{Invocation, RunState};
 use crate::workers::Workers;
-use crate::{node::*, WorkerError};
-use anyhow::Result;
+use crate::{node::*, Conflict, Graph, GraphVersion, Migrations, NormalizeReport, WorkerError};
+use crate::{
+    CancellationToken, Clock, Debugger, ExecutionObserver, ExecutionTrace, Retries, SystemClock,
+    TraceRecorder,
+};
+use crate::{Memo, MemoKey, MemoStats, SchemaError, Session, SocketRules, SocketType, StateStore};
+use semver::VersionReq;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
+type Result<T, E = EngineError> = std::result::Result<T, E>;
+
 #[derive(Error, Debug)]
 pub enum EngineError {
+    #[error("Graph has no `id`")]
+    MissingGraphId,
+    #[error("Invalid graph at `{path}`: {source}")]
+    Parse {
+        path: String,
+        source: serde_json::Error,
+    },
     #[error("Version mismatch: Engine({0}), Nodes({1})")]
     VersionMismatch(String, String),
     #[error(transparent)]
-    WorkerError(WorkerError),
-    #[error(transparent)]
-    Other(#[from] anyhow::Error),
+    WorkerError(#[from] WorkerError),
     #[error("Missing output: {node_id} {output_name}")]
     MissingOutput { node_id: i64, output_name: String },
     #[error("Invalid output type: {expected} != {actual}")]
     InvalidOutputType { expected: String, actual: String },
+    #[error("Group not found: {0}")]
+    GroupNotFound(i64),
+    #[error("Node {node_id} is not part of group {group_id}")]
+    NodeNotInGroup { node_id: i64, group_id: i64 },
+    #[error("Graph has conflicting connections: {0:?}")]
+    GraphConflicts(Vec<Conflict>),
+    #[error("Node[{node_id}]: output `{port}` should be {expected}, got {actual}")]
+    OutputTypeMismatch {
+        node_id: i64,
+        port: String,
+        expected: SocketType,
+        actual: String,
+    },
+    #[error("Migration {from} -> {to} failed: {source}")]
+    MigrationFailed {
+        from: String,
+        to: String,
+        source: anyhow::Error,
+    },
+    #[error("Node not found: {0}")]
+    NodeNotFound(i64),
+    #[error("Run was aborted")]
+    Aborted,
+    #[error("Run was cancelled")]
+    Cancelled,
+    #[error("Node[{node_id}]: timed out")]
+    Timeout { node_id: i64 },
+    #[error("Run exceeded {limit} node executions")]
+    ExecutionLimitExceeded { limit: usize },
+    #[error("Node[{node_id}]: nested deeper than {limit}")]
+    DepthLimitExceeded { node_id: i64, limit: usize },
+    #[error("Node[{node_id}]: depends on itself")]
+    CycleDetected { node_id: i64 },
+    #[error("Node[{node_id}]: outputs exceed the cache limit of {limit} bytes")]
+    CacheLimitExceeded { node_id: i64, limit: usize },
+    #[error("Graph has {nodes} nodes, more than the limit of {limit}")]
+    GraphTooLarge { nodes: usize, limit: usize },
+    #[error("Run exceeded {limit} traversal steps")]
+    TraversalLimitExceeded { limit: usize },
+}
+
+impl EngineError {
+    /// The node the error is about, if it is about one
+    pub fn node_id(&self) -> Option<i64> {
+        match self {
+            EngineError::MissingOutput { node_id, .. }
+            | EngineError::NodeNotInGroup { node_id, .. }
+            | EngineError::OutputTypeMismatch { node_id, .. }
+            | EngineError::Timeout { node_id }
+            | EngineError::DepthLimitExceeded { node_id, .. }
+            | EngineError::CycleDetected { node_id }
+            | EngineError::CacheLimitExceeded { node_id, .. } => Some(*node_id),
+            EngineError::NodeNotFound(node_id) => Some(*node_id),
+            EngineError::WorkerError(e) => e.node_id(),
+            _ => None,
+        }
+    }
+
+    /// Where in the graph JSON parsing failed, `.` for the document itself
+    pub fn json_path(&self) -> Option<&str> {
+        match self {
+            EngineError::Parse { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// This error followed by the errors that caused it
+    pub fn chain(&self) -> impl Iterator<Item = &(dyn std::error::Error + 'static)> {
+        std::iter::successors(Some(self as &(dyn std::error::Error + 'static)), |e| {
+            e.source()
+        })
+    }
+
+    fn parse(source: serde_json::Error) -> Self {
+        EngineError::Parse {
+            path: ".".to_string(),
+            source,
+        }
+    }
+}
+
+/// What `parse_value` does about graphs whose input and output connection lists disagree
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalize {
+    /// Use the graph as it is
+    #[default]
+    Off,
+    /// Mirror one-sided connections and drop dangling ones
+    Repair,
+    /// Like `Repair`, but fail with `EngineError::GraphConflicts` when anything
+    /// other than a missing side was found
+    Strict,
+}
+
+/// Guards against graphs that would run for too long or use too much memory.
+/// `None` means unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Worker calls per run
+    pub max_node_executions: Option<usize>,
+    /// How many different nodes the engine may be in the middle of at once,
+    /// following inputs and outputs. Looking up an input that already ran
+    /// doesn't go deeper.
+    pub max_depth: Option<usize>,
+    /// Total length of the `String` and `Bytes` values held for a run
+    pub max_cache_bytes: Option<usize>,
+    /// Nodes a graph may have when it is parsed
+    pub max_graph_nodes: Option<usize>,
+    /// Steps the engine may take walking inputs and outputs in a run, whether
+    /// or not they call a worker
+    pub max_traversal_steps: Option<usize>,
+}
+
+/// What a run does when a worker fails
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Stop at the first error
+    #[default]
+    FailFast,
+    /// Skip the nodes that depend on the failed one and run everything else.
+    /// `process_report` returns the errors next to the outputs, `process`
+    /// still fails with the first error once the run is over.
+    Continue,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EngineOptions {
+    pub normalize: Normalize,
+    /// Graph versions accepted after migrations have run, when not set this is
+    /// `GraphVersion::default_requirement` of the engine id. Graphs newer than
+    /// the engine are rejected either way.
+    pub compatible_versions: Option<VersionReq>,
+    /// Extra socket pairs `check_types` accepts
+    pub socket_rules: SocketRules,
+    /// Check each value a worker produces against the socket type its schema declares
+    pub check_output_types: bool,
+    /// Longest a run may take. It is checked between nodes and fails with
+    /// `EngineError::Timeout` naming the node that would have run next.
+    pub run_timeout: Option<Duration>,
+    /// Longest a call to the named worker may take. A worker can't be stopped
+    /// while it runs, so a call that overran fails once it returns.
+    pub worker_timeouts: HashMap<String, Duration>,
+    pub limits: Limits,
+    pub error_policy: ErrorPolicy,
+    pub retries: Retries,
+    /// Turn a panicking worker into `WorkerError::Panicked` instead of unwinding
+    /// out of `process`
+    pub catch_panics: bool,
+}
+
+pub(crate) type OutputCache = HashMap<i64, Rc<HashMap<String, OutputValue>>>;
+
+/// Outputs of every node that ran, by node id
+pub type NodeOutputs = HashMap<i64, HashMap<String, OutputValue>>;
+
+#[derive(Debug)]
+pub struct NodeFailure {
+    pub node_id: i64,
+    pub error: EngineError,
+}
+
+/// What a single run uses on top of the engine and its options. Everything
+/// is optional and can be combined, see `Engine::run_with`.
+#[derive(Clone, Default)]
+pub struct RunOptions<'a> {
+    pub(crate) graph_key: Option<&'a str>,
+    pub(crate) token: Option<CancellationToken>,
+    pub(crate) observers: Vec<&'a dyn ExecutionObserver>,
+}
+
+impl<'a> RunOptions<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lets workers keep state in the engine's state store under `graph_key`
+    /// and their node id. The key identifies the graph to the caller, e.g. a
+    /// document id, and should stay the same across versions.
+    pub fn graph_key(mut self, graph_key: &'a str) -> Self {
+        self.graph_key = Some(graph_key);
+        self
+    }
+
+    /// Stops the run between nodes once `token` is cancelled. Workers see it
+    /// through `Invocation::cancellation`.
+    pub fn token(mut self, token: &CancellationToken) -> Self {
+        self.token = Some(token.clone());
+        self
+    }
+
+    /// Tells `observer` about this run on top of the engine's own observers,
+    /// e.g. a `TraceRecorder`
+    pub fn observer(mut self, observer: &'a dyn ExecutionObserver) -> Self {
+        self.observers.push(observer);
+        self
+    }
+}
+
+/// Everything a run made with `Engine::run_with` produced, whether or not it
+/// finished
+#[derive(Debug)]
+pub struct RunOutcome {
+    /// Outputs of the node the run ended at, or the error that stopped the run
+    pub result: Result<HashMap<String, OutputValue>>,
+    /// Outputs of every node that ran
+    pub nodes: NodeOutputs,
+    /// Nodes that failed under `ErrorPolicy::Continue`
+    pub errors: Vec<NodeFailure>,
+    /// What the workers stored and emitted during the run
+    pub state: RunState,
+}
+
+impl RunOutcome {
+    /// The outputs of the node the run ended at, failing with the first node
+    /// error recorded under `ErrorPolicy::Continue`
+    pub fn into_result(self) -> Result<HashMap<String, OutputValue>> {
+        let outputs = self.result?;
+        match self.errors.into_iter().next() {
+            Some(failure) => Err(failure.error),
+            None => Ok(outputs),
+        }
+    }
+
+    pub fn into_report(self) -> Result<RunReport> {
+        Ok(RunReport {
+            outputs: self.result?,
+            nodes: self.nodes,
+            errors: self.errors,
+            state: self.state,
+        })
+    }
+}
+
+/// Everything a run produced, see `Engine::process_report`
+#[derive(Debug)]
+pub struct RunReport {
+    /// Outputs of the node the run ended at
+    pub outputs: HashMap<String, OutputValue>,
+    pub nodes: NodeOutputs,
+    /// Nodes that failed under `ErrorPolicy::Continue`
+    pub errors: Vec<NodeFailure>,
+    /// What the workers stored and emitted during the run
+    pub state: RunState,
 }
 
 pub struct Engine<TContext> {
     id: String,
     workers: Workers<TContext>,
+    options: EngineOptions,
+    migrations: Migrations,
+    memo: Option<Memo>,
+    observers: Vec<Box<dyn ExecutionObserver>>,
+    clock: Box<dyn Clock>,
+    state_store: Option<Box<dyn StateStore>>,
 }
 
 #[allow(dead_code)]
 impl<TContext> Engine<TContext> {
     pub fn new(id: String, workers: Workers<TContext>) -> Self {
-        Self { id, workers }
+        Self::with_options(id, workers, EngineOptions::default())
+    }
+
+    pub fn with_options(id: String, workers: Workers<TContext>, options: EngineOptions) -> Self {
+        Self {
+            id,
+            workers,
+            options,
+            migrations: Migrations::default(),
+            memo: None,
+            observers: vec![],
+            clock: Box::new(SystemClock),
+            state_store: None,
+        }
+    }
+
+    /// `observer` is told about every run of this engine
+    pub fn add_observer<O: ExecutionObserver + 'static>(&mut self, observer: O) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// `clock` waits between retries instead of the system clock
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Memoize the outputs of pure workers in `memo`
+    pub fn set_memo(&mut self, memo: Memo) {
+        self.memo = Some(memo);
+    }
+
+    /// Keep the state of stateful workers in `store`. Only runs given a graph
+    /// key, see `RunOptions::graph_key`, can reach it.
+    pub fn set_state_store<S: StateStore + 'static>(&mut self, store: S) {
+        self.state_store = Some(Box::new(store));
+    }
+
+    pub fn state_store(&self) -> Option<&dyn StateStore> {
+        self.state_store.as_deref()
+    }
+
+    pub fn memo_stats(&self) -> Option<MemoStats> {
+        self.memo.as_ref().map(|m| m.stats())
+    }
+
+    pub fn set_migrations(&mut self, migrations: Migrations) {
+        self.migrations = migrations;
     }
 
     pub fn parse_json(&self, json: &str) -> Result<HashMap<i64, Node>> {
-        let value: Value = serde_json::from_str(json)?;
+        let value: Value = serde_json::from_str(json).map_err(EngineError::parse)?;
         self.parse_value(value)
     }
 
     pub fn parse_value(&self, value: Value) -> Result<HashMap<i64, Node>> {
-        let version = value["id"]
-            .as_str()
-            .ok_or(anyhow!("Engine has no version"))?
-            .to_string();
-        if self.id != version {
-            bail!(EngineError::VersionMismatch(self.id.to_string(), version));
+        Ok(self.parse_graph_value(value)?.nodes)
+    }
+
+    pub fn parse_graph_json(&self, json: &str) -> Result<Graph> {
+        Ok(self.parse_graph_json_with_report(json)?.0)
+    }
+
+    pub fn parse_graph_value(&self, value: Value) -> Result<Graph> {
+        Ok(self.parse_graph_value_with_report(value)?.0)
+    }
+
+    /// Like `parse_graph_json`, also returning what `EngineOptions::normalize`
+    /// repaired
+    pub fn parse_graph_json_with_report(&self, json: &str) -> Result<(Graph, NormalizeReport)> {
+        let value: Value = serde_json::from_str(json).map_err(EngineError::parse)?;
+        self.parse_graph_value_with_report(value)
+    }
+
+    /// Like `parse_graph_value`, also returning what `EngineOptions::normalize`
+    /// repaired. The report is empty with `Normalize::Off`.
+    pub fn parse_graph_value_with_report(&self, value: Value) -> Result<(Graph, NormalizeReport)> {
+        value["id"].as_str().ok_or(EngineError::MissingGraphId)?;
+        if let Some(limit) = self.options.limits.max_graph_nodes {
+            let nodes = match &value["nodes"] {
+                Value::Object(nodes) => nodes.len(),
+                Value::Array(nodes) => nodes.len(),
+                _ => 0,
+            };
+            if nodes > limit {
+                return Err(EngineError::GraphTooLarge { nodes, limit });
+            }
         }
-        let nodes: HashMap<String, Node> = serde_json::from_value(value["nodes"].clone())?;
-        nodes
-            .into_iter()
-            .map(|(k, v)| Ok((k.parse::<i64>()?, v)))
-            .collect::<Result<HashMap<_, _>>>()
+        let mut graph: Graph =
+            serde_path_to_error::deserialize(value).map_err(|e| EngineError::Parse {
+                path: e.path().to_string(),
+                source: e.into_inner(),
+            })?;
+        self.upgrade(&mut graph)?;
+        let report = match self.options.normalize {
+            Normalize::Off => NormalizeReport::default(),
+            _ => graph.normalize(),
+        };
+        if self.options.normalize == Normalize::Strict && !report.conflicts.is_empty() {
+            return Err(EngineError::GraphConflicts(report.conflicts));
+        }
+        Ok((graph, report))
     }
 
-    /// Consumes engine
+    /// Runs the registered migrations on an older graph and checks that the
+    /// version it ends up at is one this engine accepts
+    fn upgrade(&self, graph: &mut Graph) -> Result<()> {
+        if self.id == graph.id {
+            return Ok(());
+        }
+        let original = graph.id.clone();
+        let (engine, mut current) = match (
+            self.id.parse::<GraphVersion>(),
+            graph.id.parse::<GraphVersion>(),
+        ) {
+            (Ok(engine), Ok(current)) if engine.name == current.name => (engine, current),
+            _ => return Err(EngineError::VersionMismatch(self.id.to_string(), original)),
+        };
+        while let Some(migration) = self.migrations.next(&current.version, &engine.version) {
+            migration
+                .apply(graph)
+                .map_err(|source| EngineError::MigrationFailed {
+                    from: current.to_string(),
+                    to: migration.to.to_string(),
+                    source,
+                })?;
+            current.version = migration.to.clone();
+            graph.id = current.to_string();
+        }
+        let compatible = self
+            .options
+            .compatible_versions
+            .clone()
+            .unwrap_or_else(|| engine.default_requirement());
+        // a graph saved by a newer engine may use things this one doesn't know
+        if current.version > engine.version || !compatible.matches(&current.version) {
+            return Err(EngineError::VersionMismatch(self.id.to_string(), original));
+        }
+        Ok(())
+    }
+
+    /// Static socket type check of every connection in `nodes`
+    pub fn check_types(&self, nodes: &HashMap<i64, Node>) -> Vec<SchemaError> {
+        self.workers.check_types(nodes, &self.options.socket_rules)
+    }
+
+    /// The engine can be reused, memoized outputs are shared between runs
     pub fn process(
-        self,
+        &self,
         context: &TContext,
         nodes: &HashMap<i64, Node>,
         start_node_id: i64,
     ) -> Result<HashMap<String, OutputValue>> {
-        let mut cache = HashMap::new();
-        let mut closed_nodes: Vec<i64> = Vec::new();
-        let end_id = self.process_nodes(
+        self.run_with(context, nodes, start_node_id, RunOptions::new())
+            .into_result()
+    }
+
+    /// Like `process`, also returning what happened to each node. The trace is
+    /// returned whether or not the run succeeded.
+    pub fn process_traced(
+        &self,
+        context: &TContext,
+        nodes: &HashMap<i64, Node>,
+        start_node_id: i64,
+    ) -> (Result<HashMap<String, OutputValue>>, ExecutionTrace) {
+        let recorder = TraceRecorder::default();
+        let result = self
+            .run_with(
+                context,
+                nodes,
+                start_node_id,
+                RunOptions::new().observer(&recorder),
+            )
+            .into_result();
+        (result, recorder.finish(nodes))
+    }
+
+    /// Like `process`, also returning the outputs of every node and, under
+    /// `ErrorPolicy::Continue`, the errors of the nodes that failed
+    pub fn process_report(
+        &self,
+        context: &TContext,
+        nodes: &HashMap<i64, Node>,
+        start_node_id: i64,
+    ) -> Result<RunReport> {
+        self.run_with(context, nodes, start_node_id, RunOptions::new())
+            .into_report()
+    }
+
+    /// Like `process`, but stops between nodes once `token` is cancelled. The
+    /// outputs of the nodes that ran are returned whether or not the run finished.
+    pub fn process_cancellable(
+        &self,
+        context: &TContext,
+        nodes: &HashMap<i64, Node>,
+        start_node_id: i64,
+        token: &CancellationToken,
+    ) -> (Result<HashMap<String, OutputValue>>, NodeOutputs) {
+        let mut outcome = self.run_with(
             context,
-            &nodes[&start_node_id],
             nodes,
-            &mut cache,
-            &mut closed_nodes,
-        )?;
-        Ok((*cache[&end_id]).clone())
+            start_node_id,
+            RunOptions::new().token(token),
+        );
+        let nodes = std::mem::take(&mut outcome.nodes);
+        (outcome.into_result(), nodes)
     }
 
-    fn process_node(
+    /// Runs from `start_node_id` with everything `options` adds to the run. The
+    /// `process_*` methods are shorthands for the common cases.
+    pub fn run_with(
         &self,
         context: &TContext,
-        node: &Node,
         nodes: &HashMap<i64, Node>,
-        cache: &mut HashMap<i64, Rc<HashMap<String, OutputValue>>>,
-        closed_nodes: &mut Vec<i64>,
-    ) -> Result<Rc<HashMap<String, OutputValue>>, EngineError> {
-        if cache.contains_key(&node.id) {
-            return Ok(cache[&node.id].clone());
-        }
-        if closed_nodes.contains(&node.id) {
-            return Ok(Rc::new(HashMap::new()));
-        }
-
-        let mut input_data: HashMap<String, OutputValue> = HashMap::new();
-        for (name, input) in &node.inputs {
-            for conn in &input.connections {
-                if !closed_nodes.contains(&conn.node) {
-                    let out =
-                        self.process_node(context, &nodes[&conn.node], nodes, cache, closed_nodes)?;
-                    input_data.insert(
-                        name.clone(),
-                        out.get(&conn.output)
-                            .ok_or_else(|| EngineError::MissingOutput {
-                                node_id: conn.node,
-                                output_name: conn.output.clone(),
-                            })?
-                            .clone(),
-                    );
-                    // if !out.clone().contains_key(&conn.output) && conn.output != "action" {
-                    //     Self::disable_node_tree(&nodes[&conn.node], nodes, closed_nodes);
-                    //     Self::disable_node_tree(node, nodes, closed_nodes);
-                    // }
-                }
-            }
-        }
-        let mut output = Rc::new(HashMap::new());
-        if !closed_nodes.contains(&node.id) {
-            output = Rc::new(self.workers.call(&node.name, context, node, input_data)?);
-            cache.insert(node.id, output.clone());
+        start_node_id: i64,
+        options: RunOptions<'_>,
+    ) -> RunOutcome {
+        let mut execution = Execution::new(
+            self,
+            context,
+            nodes,
+            start_node_id,
+            OutputCache::new(),
+            &options,
+        );
+        let result = execution.run_to_end();
+        let errors = execution.take_failures();
+        let state = execution.take_state();
+        RunOutcome {
+            result,
+            nodes: execution.into_outputs(),
+            errors,
+            state,
         }
-        Ok(output)
     }
 
-    fn process_nodes(
+    /// Starts an incremental session over `nodes`, see `Session`
+    pub fn session(&self, nodes: HashMap<i64, Node>, start_node_id: i64) -> Session<'_, TContext> {
+        Session::new(self, nodes, start_node_id)
+    }
+
+    /// Runs from `start_node_id`, reusing any node outputs already in `cache`
+    pub(crate) fn run<'a>(
+        &'a self,
+        context: &'a TContext,
+        nodes: &'a HashMap<i64, Node>,
+        start_node_id: i64,
+        cache: &mut OutputCache,
+        options: &RunOptions<'a>,
+    ) -> Result<HashMap<String, OutputValue>> {
+        let mut execution = Execution::new(
+            self,
+            context,
+            nodes,
+            start_node_id,
+            std::mem::take(cache),
+            options,
+        );
+        let result = execution.run_to_completion();
+        *cache = execution.into_cache();
+        result
+    }
+
+    /// Starts a run that can be paused at breakpoints, see `Debugger`
+    pub fn debug<'a>(
+        &'a self,
+        context: &'a TContext,
+        nodes: &'a HashMap<i64, Node>,
+        start_node_id: i64,
+    ) -> Debugger<'a, TContext> {
+        self.debug_with(context, nodes, start_node_id, RunOptions::new())
+    }
+
+    /// Like `debug`, with everything `options` adds to the run
+    pub fn debug_with<'a>(
+        &'a self,
+        context: &'a TContext,
+        nodes: &'a HashMap<i64, Node>,
+        start_node_id: i64,
+        options: RunOptions<'a>,
+    ) -> Debugger<'a, TContext> {
+        Debugger::new(Execution::new(
+            self,
+            context,
+            nodes,
+            start_node_id,
+            OutputCache::new(),
+            &options,
+        ))
+    }
+
+    pub fn options(&self) -> &EngineOptions {
+        &self.options
+    }
+
+    pub(crate) fn observers(&self) -> impl Iterator<Item = &dyn ExecutionObserver> {
+        self.observers.iter().map(|o| o.as_ref())
+    }
+
+    /// Only the nodes of `group_id` are run and connections crossing the group
+    /// boundary are ignored
+    pub fn process_group(
         &self,
         context: &TContext,
+        graph: &Graph,
+        group_id: i64,
+        start_node_id: i64,
+    ) -> Result<HashMap<String, OutputValue>> {
+        let nodes = graph
+            .group_subgraph(group_id)
+            .ok_or(EngineError::GroupNotFound(group_id))?;
+        if !nodes.contains_key(&start_node_id) {
+            return Err(EngineError::NodeNotInGroup {
+                node_id: start_node_id,
+                group_id,
+            });
+        }
+        self.process(context, &nodes, start_node_id)
+    }
+
+    /// Calls the worker of `node`, reporting it to `observer`. The flag is set
+    /// when the worker failed and the outputs only carry its error message.
+    pub(crate) fn run_node(
+        &self,
+        invocation: &Invocation<'_, TContext>,
         node: &Node,
-        nodes: &HashMap<i64, Node>,
-        cache: &mut HashMap<i64, Rc<HashMap<String, OutputValue>>>,
-        closed_nodes: &mut Vec<i64>,
-    ) -> Result<i64, EngineError> {
-        let mut id: i64 = node.id;
-        if !closed_nodes.contains(&node.id) {
-            let outputdata = self.process_node(context, node, nodes, cache, closed_nodes)?;
-            for (name, output) in node.outputs.clone() {
-                if outputdata.contains_key(&name) {
-                    for connection in &output.connections {
-                        if !closed_nodes.contains(&connection.node) {
-                            id = self.process_nodes(
-                                context,
-                                &nodes[&connection.node],
-                                nodes,
-                                cache,
-                                closed_nodes,
-                            )?;
-                        }
-                    }
-                } else if name != "action" {
-                    for connection in &output.connections {
-                        if connection.input == name.clone()
-                            && !closed_nodes.contains(&connection.node)
-                        {
-                            Self::disable_node_tree(&nodes[&connection.node], nodes, closed_nodes);
-                        }
+        input_data: HashMap<String, OutputValue>,
+        observer: &dyn ExecutionObserver,
+    ) -> Result<(HashMap<String, OutputValue>, bool), EngineError> {
+        observer.node_started(node, &input_data);
+        let started = Instant::now();
+        let result = self
+            .call_worker(invocation, node, input_data, observer)
+            .and_then(|output| {
+                if self.options.check_output_types {
+                    self.check_output_types(node, &output)?;
+                }
+                match self.options.worker_timeouts.get(&node.name) {
+                    Some(timeout) if started.elapsed() > *timeout => {
+                        Err(EngineError::Timeout { node_id: node.id })
                     }
+                    _ => Ok(output),
                 }
+            })
+            .map(|output| (output, false))
+            .or_else(|e| self.error_output(node, &e).map(|o| (o, true)).ok_or(e));
+        match &result {
+            Ok((output, _)) => observer.node_finished(node, output, started.elapsed()),
+            Err(e) => observer.node_failed(node, e, started.elapsed()),
+        }
+        result
+    }
+
+    /// The outputs of a node whose worker failed with `error`, when the node
+    /// handles failures through a connected `ERROR_OUTPUT_KEY` output
+    fn error_output(
+        &self,
+        node: &Node,
+        error: &EngineError,
+    ) -> Option<HashMap<String, OutputValue>> {
+        let connected = node
+            .outputs
+            .get(ERROR_OUTPUT_KEY)
+            .is_some_and(|o| !o.connections.is_empty());
+        if !connected {
+            return None;
+        }
+        let message = match error {
+            EngineError::WorkerError(WorkerError::NodeRunError { source, .. }) => {
+                format!("{:#}", source)
             }
+            EngineError::WorkerError(WorkerError::Panicked { message, .. }) => message.clone(),
+            _ => return None,
+        };
+        Some(HashMap::from([(
+            ERROR_OUTPUT_KEY.to_string(),
+            OutputValue::String(message),
+        )]))
+    }
+
+    fn call_worker(
+        &self,
+        invocation: &Invocation<'_, TContext>,
+        node: &Node,
+        input_data: HashMap<String, OutputValue>,
+        observer: &dyn ExecutionObserver,
+    ) -> Result<HashMap<String, OutputValue>> {
+        let memo = match &self.memo {
+            Some(memo) if self.workers.is_pure(&node.name) => memo,
+            _ => return self.call_with_retries(invocation, node, input_data, observer),
+        };
+        let version = self.workers.version(&node.name).unwrap_or_default();
+        let key = MemoKey::new(&node.name, version, node, &input_data);
+        if let Some(output) = memo.get(&key) {
+            return Ok(output);
         }
-        Ok(id)
+        let output = self.call_with_retries(invocation, node, input_data, observer)?;
+        memo.put(key, &output);
+        Ok(output)
     }
 
-    fn disable_node_tree(node: &'_ Node, nodes: &HashMap<i64, Node>, closed_nodes: &mut Vec<i64>) {
-        match node.inputs.clone().get("action") {
-            None => (),
-            Some(input) => {
-                if input.connections.len() == 1 {
-                    if !closed_nodes.contains(&node.id) {
-                        closed_nodes.push(node.id);
-                    }
-                    for output in node.outputs.clone().values() {
-                        for connection in &output.connections {
-                            let _node = &nodes[&connection.node];
-                            if let Some(input) = _node.inputs.clone().get("action") {
-                                if input
-                                    .connections
-                                    .clone()
-                                    .into_iter()
-                                    .any(|c| c.node == connection.node)
-                                {
-                                    Self::disable_node_tree(
-                                        &nodes[&connection.node],
-                                        nodes,
-                                        closed_nodes,
-                                    );
-                                }
-                            }
-                        }
-                    }
-                }
+    /// Calls the worker again with the same inputs while its retry policy allows.
+    /// Retrying stops once the run is cancelled or would run past its deadline.
+    fn call_with_retries(
+        &self,
+        invocation: &Invocation<'_, TContext>,
+        node: &Node,
+        input_data: HashMap<String, OutputValue>,
+        observer: &dyn ExecutionObserver,
+    ) -> Result<HashMap<String, OutputValue>> {
+        let policy = match self.options.retries.policy(node) {
+            Some(policy) => policy,
+            None => return Ok(self.call_isolated(invocation, node, input_data)?),
+        };
+        let mut attempt = 1;
+        loop {
+            if invocation.is_cancelled() {
+                return Err(EngineError::Cancelled);
+            }
+            if invocation.is_past_deadline() {
+                return Err(EngineError::Timeout { node_id: node.id });
+            }
+            let error = match self.call_isolated(invocation, node, input_data.clone()) {
+                Ok(output) => return Ok(output),
+                Err(e) => e,
+            };
+            let retryable = match &error {
+                WorkerError::NodeRunError { source, .. } => policy.is_retryable(source),
+                _ => false,
+            };
+            let error = EngineError::from(error);
+            if !retryable || attempt >= policy.attempts {
+                return Err(error);
+            }
+            let delay = policy.backoff.delay(attempt);
+            if invocation.is_cancelled() {
+                return Err(EngineError::Cancelled);
+            }
+            if invocation
+                .deadline()
+                .is_some_and(|deadline| Instant::now() + delay > deadline)
+            {
+                return Err(EngineError::Timeout { node_id: node.id });
             }
+            observer.node_retrying(node, attempt, &error, delay);
+            self.clock.sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    fn call_isolated(
+        &self,
+        invocation: &Invocation<'_, TContext>,
+        node: &Node,
+        input_data: HashMap<String, OutputValue>,
+    ) -> Result<HashMap<String, OutputValue>, WorkerError> {
+        if !self.options.catch_panics {
+            return self
+                .workers
+                .invoke(&node.name, invocation, node, input_data);
+        }
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            self.workers
+                .invoke(&node.name, invocation, node, input_data)
+        }))
+        .unwrap_or_else(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(WorkerError::Panicked {
+                node_id: node.id,
+                worker: node.name.clone(),
+                message,
+            })
+        })
+    }
+
+    fn check_output_types(
+        &self,
+        node: &Node,
+        output: &HashMap<String, OutputValue>,
+    ) -> Result<(), EngineError> {
+        match self.workers.schema(&node.name) {
+            Some(schema) => schema.check_outputs(node.id, output),
+            None => Ok(()),
         }
     }
 }
@@ -0,0 +1,625 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::engine::{
+    Engine, EngineError, ErrorPolicy, NodeFailure, NodeOutputs, OutputCache, RunOptions,
+};
+use crate::invocation::{Invocation, RunState};
+use crate::node::*;
+use crate::{CancellationToken, ExecutionObserver, Observers, SkipReason};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::time::Instant;
+
+/// Where an `Execution` handed control back
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Step {
+    /// The inputs of this node are gathered and its worker is next to run
+    Pending(NodeId),
+    /// Nothing is left to run, the run ended at this node
+    Finished(NodeId),
+}
+
+/// (port on this node, node on the other side, port on the other node)
+type Link = (String, NodeId, String);
+
+struct Follow {
+    node_id: NodeId,
+    /// Last node reached downstream of this one
+    end_id: NodeId,
+    outputs: Option<Produced>,
+}
+
+/// What a followed node produced and how far through its outgoing links we are
+struct Produced {
+    data: Rc<HashMap<String, OutputValue>>,
+    links: Vec<Link>,
+    index: usize,
+}
+
+struct Gather {
+    node_id: NodeId,
+    entered: bool,
+    inputs: Vec<Link>,
+    index: usize,
+    input_data: HashMap<String, OutputValue>,
+}
+
+enum Frame {
+    /// Runs a node and then every node its produced outputs lead to
+    Follow(Follow),
+    /// Gathers the inputs of a node, running the nodes they come from first
+    Gather(Gather),
+}
+
+enum Returned {
+    Output(Rc<HashMap<String, OutputValue>>),
+    End(NodeId),
+    /// The node failed or was skipped because a node it depends on failed
+    Failed,
+}
+
+/// A single run of the engine, kept on an explicit stack instead of the call
+/// stack so it can stop before every worker call and be resumed later
+pub(crate) struct Execution<'a, TContext> {
+    engine: &'a Engine<TContext>,
+    context: &'a TContext,
+    nodes: &'a HashMap<NodeId, Node>,
+    start_node_id: NodeId,
+    cache: OutputCache,
+    closed_nodes: HashSet<NodeId>,
+    /// Nodes whose outgoing links were all followed, with the node that walk
+    /// ended at. Reaching one of them again doesn't walk its links again.
+    followed: HashMap<NodeId, NodeId>,
+    /// Frames handled so far, see `Limits::max_traversal_steps`
+    steps: usize,
+    stack: Vec<Frame>,
+    returned: Option<Returned>,
+    pending: Option<(NodeId, HashMap<String, OutputValue>)>,
+    observer: Observers<'a>,
+    started: Instant,
+    end_id: Option<NodeId>,
+    aborted: bool,
+    token: Option<CancellationToken>,
+    deadline: Option<Instant>,
+    executions: usize,
+    cache_bytes: usize,
+    /// Nodes that failed and everything downstream of them, under `ErrorPolicy::Continue`
+    failed: HashSet<NodeId>,
+    failures: Vec<NodeFailure>,
+    state: RunState,
+    /// Key of the persistent node state, only kept when there is one
+    graph_key: Option<&'a str>,
+}
+
+impl<'a, TContext> Execution<'a, TContext> {
+    pub(crate) fn new(
+        engine: &'a Engine<TContext>,
+        context: &'a TContext,
+        nodes: &'a HashMap<NodeId, Node>,
+        start_node_id: NodeId,
+        cache: OutputCache,
+        options: &RunOptions<'a>,
+    ) -> Self {
+        let observer = Observers(
+            engine
+                .observers()
+                .chain(options.observers.iter().copied())
+                .collect(),
+        );
+        observer.run_started(start_node_id);
+        let started = Instant::now();
+        Self {
+            engine,
+            context,
+            nodes,
+            start_node_id,
+            closed_nodes: HashSet::new(),
+            followed: HashMap::new(),
+            steps: 0,
+            stack: vec![Frame::Follow(Follow {
+                node_id: start_node_id,
+                end_id: start_node_id,
+                outputs: None,
+            })],
+            returned: None,
+            pending: None,
+            observer,
+            started,
+            end_id: None,
+            aborted: false,
+            token: options.token.clone(),
+            deadline: engine.options().run_timeout.map(|t| started + t),
+            executions: 0,
+            failed: HashSet::new(),
+            failures: Vec::new(),
+            state: RunState::default(),
+            graph_key: options.graph_key,
+            cache_bytes: cache.values().map(|o| output_bytes(o)).sum(),
+            cache,
+        }
+    }
+
+    pub(crate) fn nodes(&self) -> &'a HashMap<NodeId, Node> {
+        self.nodes
+    }
+
+    pub(crate) fn cache(&self) -> &OutputCache {
+        &self.cache
+    }
+
+    pub(crate) fn into_cache(self) -> OutputCache {
+        self.cache
+    }
+
+    /// Node errors recorded under `ErrorPolicy::Continue`
+    pub(crate) fn take_failures(&mut self) -> Vec<NodeFailure> {
+        std::mem::take(&mut self.failures)
+    }
+
+    pub(crate) fn state(&self) -> &RunState {
+        &self.state
+    }
+
+    pub(crate) fn take_state(&mut self) -> RunState {
+        std::mem::take(&mut self.state)
+    }
+
+    pub(crate) fn into_outputs(self) -> NodeOutputs {
+        self.cache
+            .into_iter()
+            .map(|(id, output)| (id, (*output).clone()))
+            .collect()
+    }
+
+    /// The node whose worker runs next and the inputs gathered for it
+    pub(crate) fn pending(&self) -> Option<(&'a Node, &HashMap<String, OutputValue>)> {
+        self.pending
+            .as_ref()
+            .map(|(id, input_data)| (&self.nodes[id], input_data))
+    }
+
+    /// Runs until the next worker call or the end of the run, without making
+    /// the call itself
+    pub(crate) fn advance(&mut self) -> Result<Step, EngineError> {
+        if self.aborted {
+            return Err(EngineError::Aborted);
+        }
+        if let Some((id, _)) = &self.pending {
+            return Ok(Step::Pending(*id));
+        }
+        if let Some(end_id) = self.end_id {
+            return Ok(Step::Finished(end_id));
+        }
+        match self.next_step() {
+            Ok(Step::Finished(end_id)) => {
+                self.end_id = Some(end_id);
+                self.observer
+                    .run_finished(self.start_node_id, self.started.elapsed(), None);
+                Ok(Step::Finished(end_id))
+            }
+            Ok(Step::Pending(id)) => match self.interruption(id) {
+                Some(e) => Err(self.fail(e)),
+                None => Ok(Step::Pending(id)),
+            },
+            Err(e) => Err(self.fail(e)),
+        }
+    }
+
+    /// Calls the worker of the pending node, if there is one
+    pub(crate) fn run_pending(&mut self) -> Result<(), EngineError> {
+        let (node_id, input_data) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        let limits = self.engine.options().limits;
+        self.executions += 1;
+        if let Some(limit) = limits.max_node_executions {
+            if self.executions > limit {
+                return Err(self.fail(EngineError::ExecutionLimitExceeded { limit }));
+            }
+        }
+        let node = &self.nodes[&node_id];
+        let mut invocation = Invocation::new(self.context, &self.state, node_id);
+        if let (Some(graph_key), Some(store)) = (self.graph_key, self.engine.state_store()) {
+            invocation = invocation.with_store(graph_key, store);
+        }
+        if let Some(token) = &self.token {
+            invocation = invocation.with_token(token);
+        }
+        if let Some(deadline) = self.deadline {
+            invocation = invocation.with_deadline(deadline);
+        }
+        match self
+            .engine
+            .run_node(&invocation, node, input_data, &self.observer)
+        {
+            Ok((output, failed)) => {
+                if failed {
+                    // only the nodes behind the error output get anything
+                    self.skip_downstream(node_id, Some(ERROR_OUTPUT_KEY));
+                }
+                self.cache_bytes += output_bytes(&output);
+                if let Some(limit) = limits.max_cache_bytes {
+                    if self.cache_bytes > limit {
+                        return Err(self.fail(EngineError::CacheLimitExceeded { node_id, limit }));
+                    }
+                }
+                let output = Rc::new(output);
+                self.cache.insert(node_id, output.clone());
+                self.returned = Some(Returned::Output(output));
+                Ok(())
+            }
+            Err(e) => {
+                // a worker that noticed the cancellation fails with its own error
+                if let Some(e) = self.interruption(node_id) {
+                    return Err(self.fail(e));
+                }
+                if self.engine.options().error_policy == ErrorPolicy::Continue {
+                    self.skip_dependents(node_id);
+                    self.failures.push(NodeFailure { node_id, error: e });
+                    self.returned = Some(Returned::Failed);
+                    return Ok(());
+                }
+                Err(self.fail(e))
+            }
+        }
+    }
+
+    /// Runs everything that is left and returns the outputs of the node the run ended at
+    pub(crate) fn run_to_end(&mut self) -> Result<HashMap<String, OutputValue>, EngineError> {
+        loop {
+            match self.advance()? {
+                Step::Pending(_) => self.run_pending()?,
+                Step::Finished(end_id) => return Ok(self.outputs(end_id)),
+            }
+        }
+    }
+
+    /// Like `run_to_end`, also failing with the first node error recorded under
+    /// `ErrorPolicy::Continue`
+    pub(crate) fn run_to_completion(
+        &mut self,
+    ) -> Result<HashMap<String, OutputValue>, EngineError> {
+        let outputs = self.run_to_end()?;
+        match self.failures.drain(..).next() {
+            Some(failure) => Err(failure.error),
+            None => Ok(outputs),
+        }
+    }
+
+    pub(crate) fn outputs(&self, id: NodeId) -> HashMap<String, OutputValue> {
+        self.cache
+            .get(&id)
+            .map(|o| (**o).clone())
+            .unwrap_or_default()
+    }
+
+    /// Stops the run, observers see it fail with `EngineError::Aborted`
+    pub(crate) fn abort(&mut self) {
+        if !self.aborted && self.end_id.is_none() {
+            self.fail(EngineError::Aborted);
+        }
+    }
+
+    fn fail(&mut self, error: EngineError) -> EngineError {
+        self.aborted = true;
+        self.stack.clear();
+        self.pending = None;
+        self.observer
+            .run_finished(self.start_node_id, self.started.elapsed(), Some(&error));
+        error
+    }
+
+    /// Why the run must stop before running `node_id`, if it must
+    fn interruption(&self, node_id: NodeId) -> Option<EngineError> {
+        if self.token.as_ref().is_some_and(|t| t.is_cancelled()) {
+            return Some(EngineError::Cancelled);
+        }
+        match self.deadline {
+            Some(deadline) if Instant::now() > deadline => Some(EngineError::Timeout { node_id }),
+            _ => None,
+        }
+    }
+
+    fn push_child(&mut self, frame: Frame) -> Result<(), EngineError> {
+        let node_id = frame.node_id();
+        // a node already gathering its inputs, or already followed, further down
+        // the stack depends on itself
+        let repeated = self.stack.iter().any(|f| match (f, &frame) {
+            (Frame::Follow(a), Frame::Follow(b)) => a.node_id == b.node_id,
+            (Frame::Gather(a), Frame::Gather(b)) => a.node_id == b.node_id,
+            _ => false,
+        });
+        if repeated {
+            return Err(EngineError::CycleDetected { node_id });
+        }
+        let cached = matches!(frame, Frame::Gather(_)) && self.cache.contains_key(&node_id);
+        if let Some(limit) = self.engine.options().limits.max_depth.filter(|_| !cached) {
+            let mut nodes: HashSet<NodeId> = self.stack.iter().map(Frame::node_id).collect();
+            nodes.insert(node_id);
+            if nodes.len() > limit {
+                return Err(EngineError::DepthLimitExceeded { node_id, limit });
+            }
+        }
+        self.stack.push(frame);
+        Ok(())
+    }
+
+    fn node(&self, id: NodeId) -> Result<&'a Node, EngineError> {
+        self.nodes.get(&id).ok_or(EngineError::NodeNotFound(id))
+    }
+
+    fn is_closed(&self, id: NodeId) -> bool {
+        self.closed_nodes.contains(&id)
+    }
+
+    fn next_step(&mut self) -> Result<Step, EngineError> {
+        'frames: loop {
+            let frame = match self.stack.pop() {
+                Some(frame) => frame,
+                None => {
+                    return Ok(match self.returned.take() {
+                        Some(Returned::End(end_id)) => Step::Finished(end_id),
+                        _ => Step::Finished(self.start_node_id),
+                    })
+                }
+            };
+            self.steps += 1;
+            if let Some(limit) = self.engine.options().limits.max_traversal_steps {
+                if self.steps > limit {
+                    return Err(EngineError::TraversalLimitExceeded { limit });
+                }
+            }
+            match frame {
+                Frame::Follow(follow) if self.followed.contains_key(&follow.node_id) => {
+                    self.returned = Some(Returned::End(self.followed[&follow.node_id]));
+                }
+                Frame::Follow(mut follow) => match follow.outputs.take() {
+                    None => match self.returned.take() {
+                        Some(Returned::Output(data)) => {
+                            let links = self
+                                .node(follow.node_id)?
+                                .outputs
+                                .iter()
+                                .flat_map(|(name, output)| {
+                                    output
+                                        .connections
+                                        .iter()
+                                        .map(move |c| (name.clone(), c.node, c.input.clone()))
+                                })
+                                .collect();
+                            follow.outputs = Some(Produced {
+                                data,
+                                links,
+                                index: 0,
+                            });
+                            self.stack.push(Frame::Follow(follow));
+                        }
+                        _ if self.is_closed(follow.node_id)
+                            || self.failed.contains(&follow.node_id) =>
+                        {
+                            self.returned = Some(Returned::End(follow.node_id));
+                        }
+                        Some(Returned::Failed) => {
+                            self.returned = Some(Returned::End(follow.node_id));
+                        }
+                        _ => {
+                            let node_id = follow.node_id;
+                            self.stack.push(Frame::Follow(follow));
+                            self.push_child(Frame::Gather(Gather::new(node_id)))?;
+                        }
+                    },
+                    Some(mut produced) => {
+                        if let Some(Returned::End(end_id)) = self.returned.take() {
+                            follow.end_id = end_id;
+                        }
+                        let (name, to, input) = match produced.links.get(produced.index) {
+                            Some(link) => link.clone(),
+                            None => {
+                                self.followed.insert(follow.node_id, follow.end_id);
+                                self.returned = Some(Returned::End(follow.end_id));
+                                continue;
+                            }
+                        };
+                        produced.index += 1;
+                        let is_produced = produced.data.contains_key(&name);
+                        let node_id = follow.node_id;
+                        follow.outputs = Some(produced);
+                        self.stack.push(Frame::Follow(follow));
+                        if is_produced {
+                            if !self.is_closed(to) {
+                                self.push_child(Frame::Follow(Follow {
+                                    node_id: to,
+                                    end_id: to,
+                                    outputs: None,
+                                }))?;
+                            }
+                        } else if name != "action" && input == name && !self.is_closed(to) {
+                            let reason = SkipReason::OutputNotProduced {
+                                node_id,
+                                output: name,
+                            };
+                            self.disable_node_tree(to, &reason)?;
+                        }
+                    }
+                },
+                Frame::Gather(mut gather) => {
+                    if !gather.entered {
+                        gather.entered = true;
+                        if let Some(output) = self.cache.get(&gather.node_id) {
+                            self.returned = Some(Returned::Output(output.clone()));
+                            continue;
+                        }
+                        if self.failed.contains(&gather.node_id) {
+                            self.returned = Some(Returned::Failed);
+                            continue;
+                        }
+                        if self.is_closed(gather.node_id) {
+                            self.returned = Some(Returned::Output(Rc::new(HashMap::new())));
+                            continue;
+                        }
+                        gather.inputs = self
+                            .node(gather.node_id)?
+                            .inputs
+                            .iter()
+                            .flat_map(|(name, input)| {
+                                input
+                                    .connections
+                                    .iter()
+                                    .map(move |c| (name.clone(), c.node, c.output.clone()))
+                            })
+                            .collect();
+                    }
+                    match self.returned.take() {
+                        Some(Returned::Output(out)) => {
+                            let (name, from, output) = &gather.inputs[gather.index - 1];
+                            let value = out
+                                .get(output)
+                                .ok_or_else(|| EngineError::MissingOutput {
+                                    node_id: *from,
+                                    output_name: output.clone(),
+                                })?
+                                .clone();
+                            gather.input_data.insert(name.clone(), value);
+                        }
+                        Some(Returned::Failed) => {
+                            self.returned = Some(Returned::Failed);
+                            continue;
+                        }
+                        _ => (),
+                    }
+                    while let Some((_, from, _)) = gather.inputs.get(gather.index) {
+                        let from = *from;
+                        gather.index += 1;
+                        if !self.is_closed(from) {
+                            self.stack.push(Frame::Gather(gather));
+                            self.push_child(Frame::Gather(Gather::new(from)))?;
+                            continue 'frames;
+                        }
+                    }
+                    if self.failed.contains(&gather.node_id) {
+                        self.returned = Some(Returned::Failed);
+                        continue;
+                    }
+                    if self.is_closed(gather.node_id) {
+                        self.returned = Some(Returned::Output(Rc::new(HashMap::new())));
+                        continue;
+                    }
+                    self.pending = Some((gather.node_id, gather.input_data));
+                    return Ok(Step::Pending(gather.node_id));
+                }
+            }
+        }
+    }
+
+    /// Marks `node_id` as failed and skips every node downstream of it that
+    /// hasn't run yet
+    fn skip_dependents(&mut self, node_id: NodeId) {
+        self.failed.insert(node_id);
+        self.skip_downstream(node_id, None);
+    }
+
+    /// Skips every node downstream of the failed `node_id` that hasn't run yet,
+    /// apart from those only reached through its `except` output
+    fn skip_downstream(&mut self, node_id: NodeId, except: Option<&str>) {
+        let reason = SkipReason::UpstreamFailed { node_id };
+        let mut pending = vec![node_id];
+        while let Some(id) = pending.pop() {
+            let node = match self.nodes.get(&id) {
+                Some(node) => node,
+                None => continue,
+            };
+            for (name, output) in &node.outputs {
+                if id == node_id && except == Some(name.as_str()) {
+                    continue;
+                }
+                for connection in &output.connections {
+                    if self.cache.contains_key(&connection.node)
+                        || !self.failed.insert(connection.node)
+                    {
+                        continue;
+                    }
+                    if let Some(next) = self.nodes.get(&connection.node) {
+                        self.observer.node_skipped(next, &reason);
+                    }
+                    pending.push(connection.node);
+                }
+            }
+        }
+    }
+
+    /// Skips `id` when its only `action` input is not coming, and every node
+    /// whose `action` input comes from a skipped node
+    fn disable_node_tree(&mut self, id: NodeId, reason: &SkipReason) -> Result<(), EngineError> {
+        let mut pending = vec![(id, reason.clone())];
+        while let Some((id, reason)) = pending.pop() {
+            if self.is_closed(id) {
+                continue;
+            }
+            let node = self.node(id)?;
+            match node.inputs.get("action") {
+                Some(input) if input.connections.len() == 1 => (),
+                _ => continue,
+            }
+            self.closed_nodes.insert(id);
+            self.observer.node_skipped(node, &reason);
+            for output in node.outputs.values() {
+                for connection in &output.connections {
+                    let next = self.node(connection.node)?;
+                    let gated = next
+                        .inputs
+                        .get("action")
+                        .is_some_and(|input| input.connections.iter().any(|c| c.node == id));
+                    if gated && !self.is_closed(next.id) {
+                        pending.push((next.id, SkipReason::UpstreamSkipped { node_id: id }));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Frame {
+    fn node_id(&self) -> NodeId {
+        match self {
+            Frame::Follow(follow) => follow.node_id,
+            Frame::Gather(gather) => gather.node_id,
+        }
+    }
+}
+
+impl Gather {
+    fn new(node_id: NodeId) -> Self {
+        Self {
+            node_id,
+            entered: false,
+            inputs: Vec::new(),
+            index: 0,
+            input_data: HashMap::new(),
+        }
+    }
+}
+
+/// Bytes held by the `String` and `Bytes` values of `output`
+fn output_bytes(output: &HashMap<String, OutputValue>) -> usize {
+    output
+        .values()
+        .map(|value| match value {
+            OutputValue::String(s) => s.len(),
+            OutputValue::Bytes(b) => b.len(),
+            _ => 0,
+        })
+        .sum()
+}
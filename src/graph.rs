@@ -0,0 +1,782 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::group::*;
+use crate::node::*;
+use crate::target::*;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum GraphError {
+    #[error("Node not found: {0}")]
+    NodeNotFound(NodeId),
+    #[error("Connection not found: {0}")]
+    ConnectionNotFound(Connection),
+    #[error("Node {node_id} has no port `{port}`")]
+    PortNotFound { node_id: NodeId, port: String },
+    #[error("Node {node_id} already has a port `{port}`")]
+    PortExists { node_id: NodeId, port: String },
+}
+
+/// One edge of the graph, from an output port to an input port
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Connection {
+    pub from: NodeId,
+    pub output: String,
+    pub to: NodeId,
+    pub input: String,
+}
+
+impl Connection {
+    pub fn new(from: NodeId, output: &str, to: NodeId, input: &str) -> Self {
+        Self {
+            from,
+            output: output.to_string(),
+            to,
+            input: input.to_string(),
+        }
+    }
+}
+
+impl Display for Connection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.{} -> {}.{}",
+            self.from, self.output, self.to, self.input
+        )
+    }
+}
+
+/// The whole editor document. Anything the engine doesn't use is kept as is so
+/// that serializing a parsed graph gives back JSON the editor can open.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(from = "Document")]
+pub struct Graph {
+    pub id: String,
+    pub nodes: HashMap<i64, Node>,
+    pub groups: Groups,
+    /// How `groups` was written in the document, it is written back the same way
+    pub group_layout: GroupLayout,
+    /// `None` when the document has no `comments` field
+    pub comments: Option<Vec<Value>>,
+    pub extra: Map<String, Value>,
+}
+
+/// The editor document as read from JSON
+#[derive(Deserialize)]
+struct Document {
+    id: String,
+    #[serde(deserialize_with = "deserialize_nodes")]
+    nodes: HashMap<i64, Node>,
+    #[serde(default, deserialize_with = "deserialize_groups")]
+    groups: (Groups, GroupLayout),
+    #[serde(default)]
+    comments: Option<Vec<Value>>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+impl From<Document> for Graph {
+    fn from(document: Document) -> Self {
+        let (groups, group_layout) = document.groups;
+        Self {
+            id: document.id,
+            nodes: document.nodes,
+            groups,
+            group_layout,
+            comments: document.comments,
+            extra: document.extra,
+        }
+    }
+}
+
+impl Serialize for Graph {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        #[serde(untagged)]
+        enum GroupsRef<'a> {
+            Map(&'a Groups),
+            List(Vec<&'a Group>),
+        }
+
+        #[derive(Serialize)]
+        struct DocumentRef<'a> {
+            id: &'a str,
+            nodes: &'a HashMap<i64, Node>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            groups: Option<GroupsRef<'a>>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            comments: Option<&'a Vec<Value>>,
+            #[serde(flatten)]
+            extra: &'a Map<String, Value>,
+        }
+
+        let groups = match self.group_layout {
+            _ if self.groups.is_empty() => None,
+            GroupLayout::Map => Some(GroupsRef::Map(&self.groups)),
+            GroupLayout::List => {
+                let mut list: Vec<&Group> = self.groups.values().collect();
+                list.sort_by_key(|g| g.id);
+                Some(GroupsRef::List(list))
+            }
+        };
+        DocumentRef {
+            id: &self.id,
+            nodes: &self.nodes,
+            groups,
+            comments: self.comments.as_ref(),
+            extra: &self.extra,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl Graph {
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn group(&self, group_id: i64) -> Option<&Group> {
+        self.groups.get(&group_id)
+    }
+
+    /// Nodes belonging to the group, either listed by the group or pointing at it
+    /// through `Node.group`.
+    pub fn nodes_in_group(&self, group_id: i64) -> Vec<&Node> {
+        let group = self.groups.get(&group_id);
+        let mut nodes: Vec<&Node> = self
+            .nodes
+            .values()
+            .filter(|n| {
+                group.map(|g| g.contains(n.id)).unwrap_or(false) || n.group == Some(group_id)
+            })
+            .collect();
+        nodes.sort_by_key(|n| n.id);
+        nodes
+    }
+
+    pub fn group_of(&self, node_id: i64) -> Option<&Group> {
+        self.groups
+            .values()
+            .find(|g| g.contains(node_id))
+            .or_else(|| {
+                self.nodes
+                    .get(&node_id)
+                    .and_then(|n| n.group)
+                    .and_then(|id| self.groups.get(&id))
+            })
+    }
+
+    /// Nodes of a single group with every connection that leaves the group removed,
+    /// so the result can be handed to `Engine::process` on its own.
+    pub fn group_subgraph(&self, group_id: i64) -> Option<HashMap<i64, Node>> {
+        self.groups.get(&group_id)?;
+        let ids: Vec<i64> = self.nodes_in_group(group_id).iter().map(|n| n.id).collect();
+        Some(
+            ids.iter()
+                .map(|id| {
+                    let mut node = self.nodes[id].clone();
+                    for input in node.inputs.values_mut() {
+                        input.connections.retain(|c| ids.contains(&c.node));
+                    }
+                    for output in node.outputs.values_mut() {
+                        output.connections.retain(|c| ids.contains(&c.node));
+                    }
+                    (*id, node)
+                })
+                .collect(),
+        )
+    }
+
+    /// Every connection in the graph, read from the output side
+    pub fn connections(&self) -> Vec<Connection> {
+        let mut connections: Vec<Connection> = self
+            .nodes
+            .values()
+            .flat_map(|node| {
+                node.outputs.iter().flat_map(move |(name, output)| {
+                    output
+                        .connections
+                        .iter()
+                        .map(move |c| Connection::new(node.id, name, c.node, &c.input))
+                })
+            })
+            .collect();
+        connections.sort();
+        connections
+    }
+
+    pub fn next_node_id(&self) -> NodeId {
+        self.nodes.keys().max().map(|id| id + 1).unwrap_or(1)
+    }
+
+    /// Connects output `output` of `from` to input `input` of `to`, on both nodes
+    pub fn connect(
+        &mut self,
+        from: NodeId,
+        output: &str,
+        to: NodeId,
+        input: &str,
+    ) -> Result<(), GraphError> {
+        if !self.nodes.contains_key(&to) {
+            return Err(GraphError::NodeNotFound(to));
+        }
+        let connections = &mut self
+            .nodes
+            .get_mut(&from)
+            .ok_or(GraphError::NodeNotFound(from))?
+            .outputs
+            .entry(output.to_string())
+            .or_insert_with(|| Output {
+                connections: vec![],
+            })
+            .connections;
+        if !connections.iter().any(|c| c.node == to && c.input == input) {
+            connections.push(OutputConnection {
+                node: to,
+                input: input.to_string(),
+                data: Value::Object(Map::new()),
+            });
+        }
+        let connections = &mut self
+            .nodes
+            .get_mut(&to)
+            .unwrap()
+            .inputs
+            .entry(input.to_string())
+            .or_insert_with(|| Input {
+                connections: vec![],
+            })
+            .connections;
+        if !connections
+            .iter()
+            .any(|c| c.node == from && c.output == output)
+        {
+            connections.push(InputConnection {
+                node: from,
+                output: output.to_string(),
+                data: Value::Object(Map::new()),
+            });
+        }
+        Ok(())
+    }
+
+    /// Removes the connection from both nodes. Ports are left in place even when
+    /// they end up with no connections, the same as the editor does.
+    pub fn disconnect(
+        &mut self,
+        from: NodeId,
+        output: &str,
+        to: NodeId,
+        input: &str,
+    ) -> Result<(), GraphError> {
+        let mut found = false;
+        if let Some(o) = self
+            .nodes
+            .get_mut(&from)
+            .and_then(|n| n.outputs.get_mut(output))
+        {
+            let len = o.connections.len();
+            o.connections
+                .retain(|c| !(c.node == to && c.input == input));
+            found |= o.connections.len() != len;
+        }
+        if let Some(i) = self
+            .nodes
+            .get_mut(&to)
+            .and_then(|n| n.inputs.get_mut(input))
+        {
+            let len = i.connections.len();
+            i.connections
+                .retain(|c| !(c.node == from && c.output == output));
+            found |= i.connections.len() != len;
+        }
+        if found {
+            Ok(())
+        } else {
+            Err(GraphError::ConnectionNotFound(Connection::new(
+                from, output, to, input,
+            )))
+        }
+    }
+
+    /// Replaces connection `old` with `new`
+    pub fn rewire(&mut self, old: &Connection, new: &Connection) -> Result<(), GraphError> {
+        for id in [new.from, new.to] {
+            if !self.nodes.contains_key(&id) {
+                return Err(GraphError::NodeNotFound(id));
+            }
+        }
+        self.disconnect(old.from, &old.output, old.to, &old.input)?;
+        self.connect(new.from, &new.output, new.to, &new.input)
+    }
+
+    /// Removes the node together with every connection to or from it
+    pub fn remove_node(&mut self, id: NodeId) -> Result<Node, GraphError> {
+        let node = self.nodes.remove(&id).ok_or(GraphError::NodeNotFound(id))?;
+        for other in self.nodes.values_mut() {
+            for input in other.inputs.values_mut() {
+                input.connections.retain(|c| c.node != id);
+            }
+            for output in other.outputs.values_mut() {
+                output.connections.retain(|c| c.node != id);
+            }
+        }
+        for group in self.groups.values_mut() {
+            group.nodes.retain(|n| *n != id);
+        }
+        Ok(node)
+    }
+
+    pub fn rename_input(&mut self, id: NodeId, old: &str, new: &str) -> Result<(), GraphError> {
+        let node = self
+            .nodes
+            .get_mut(&id)
+            .ok_or(GraphError::NodeNotFound(id))?;
+        if node.inputs.contains_key(new) {
+            return Err(GraphError::PortExists {
+                node_id: id,
+                port: new.to_string(),
+            });
+        }
+        let input = node.inputs.remove(old).ok_or(GraphError::PortNotFound {
+            node_id: id,
+            port: old.to_string(),
+        })?;
+        node.inputs.insert(new.to_string(), input);
+        for other in self.nodes.values_mut() {
+            for output in other.outputs.values_mut() {
+                for c in output.connections.iter_mut() {
+                    if c.node == id && c.input == old {
+                        c.input = new.to_string();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rename_output(&mut self, id: NodeId, old: &str, new: &str) -> Result<(), GraphError> {
+        let node = self
+            .nodes
+            .get_mut(&id)
+            .ok_or(GraphError::NodeNotFound(id))?;
+        if node.outputs.contains_key(new) {
+            return Err(GraphError::PortExists {
+                node_id: id,
+                port: new.to_string(),
+            });
+        }
+        let output = node.outputs.remove(old).ok_or(GraphError::PortNotFound {
+            node_id: id,
+            port: old.to_string(),
+        })?;
+        node.outputs.insert(new.to_string(), output);
+        for other in self.nodes.values_mut() {
+            for input in other.inputs.values_mut() {
+                for c in input.connections.iter_mut() {
+                    if c.node == id && c.output == old {
+                        c.output = new.to_string();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies all nodes, groups and comments of `other` into this graph. Node and
+    /// group ids of `other` are renumbered after the existing ones; the returned
+    /// map gives the new id of each node.
+    pub fn merge(&mut self, other: Graph) -> HashMap<NodeId, NodeId> {
+        let first_node = self.next_node_id();
+        let first_group = self.groups.keys().max().map(|id| id + 1).unwrap_or(1);
+        let mut ids: Vec<NodeId> = other.nodes.keys().copied().collect();
+        ids.sort_unstable();
+        let node_map: HashMap<NodeId, NodeId> = ids
+            .into_iter()
+            .enumerate()
+            .map(|(i, id)| (id, first_node + i as i64))
+            .collect();
+        let mut group_ids: Vec<i64> = other.groups.keys().copied().collect();
+        group_ids.sort_unstable();
+        let group_map: HashMap<i64, i64> = group_ids
+            .into_iter()
+            .enumerate()
+            .map(|(i, id)| (id, first_group + i as i64))
+            .collect();
+
+        // the map keys are the ids, `node.id` may disagree in a graph built by hand
+        for (id, mut node) in other.nodes {
+            node.id = node_map[&id];
+            node.group = node.group.and_then(|g| group_map.get(&g).copied());
+            // connections to nodes outside `other` would point at unrelated nodes here
+            for input in node.inputs.values_mut() {
+                input
+                    .connections
+                    .retain_mut(|c| match node_map.get(&c.node) {
+                        Some(id) => {
+                            c.node = *id;
+                            true
+                        }
+                        None => false,
+                    });
+            }
+            for output in node.outputs.values_mut() {
+                output
+                    .connections
+                    .retain_mut(|c| match node_map.get(&c.node) {
+                        Some(id) => {
+                            c.node = *id;
+                            true
+                        }
+                        None => false,
+                    });
+            }
+            self.nodes.insert(node.id, node);
+        }
+        for (id, mut group) in other.groups {
+            group.id = group_map[&id];
+            group.nodes = group
+                .nodes
+                .iter()
+                .filter_map(|n| node_map.get(n).copied())
+                .collect();
+            self.groups.insert(group.id, group);
+        }
+        if let Some(comments) = other.comments {
+            self.comments.get_or_insert_with(Vec::new).extend(comments);
+        }
+        node_map
+    }
+}
+
+/// Assembles a graph in code, keeping both sides of every connection in step.
+#[derive(Default)]
+pub struct GraphBuilder {
+    graph: Graph,
+}
+
+impl GraphBuilder {
+    pub fn new(id: &str) -> Self {
+        Self {
+            graph: Graph::new(id),
+        }
+    }
+
+    pub fn add_node(&mut self, name: &str, data: HashMap<String, Value>) -> NodeId {
+        let id = self.graph.next_node_id();
+        self.graph.nodes.insert(
+            id,
+            Node {
+                id,
+                name: name.to_string(),
+                data,
+                group: None,
+                position: None,
+                inputs: HashMap::new(),
+                outputs: HashMap::new(),
+                extra: Map::new(),
+            },
+        );
+        id
+    }
+
+    pub fn set_position(&mut self, id: NodeId, x: f64, y: f64) -> Result<&mut Self, GraphError> {
+        self.graph
+            .nodes
+            .get_mut(&id)
+            .ok_or(GraphError::NodeNotFound(id))?
+            .position = Some(vec![x, y]);
+        Ok(self)
+    }
+
+    /// Connects output `output` of `from` to input `input` of `to`
+    pub fn connect(
+        &mut self,
+        from: NodeId,
+        output: &str,
+        to: NodeId,
+        input: &str,
+    ) -> Result<&mut Self, GraphError> {
+        self.graph.connect(from, output, to, input)?;
+        Ok(self)
+    }
+
+    pub fn build(self) -> Graph {
+        self.graph
+    }
+
+    pub fn build_nodes(self) -> HashMap<NodeId, Node> {
+        self.graph.nodes
+    }
+}
+
+fn parse_key<E: de::Error>(key: &str) -> Result<i64, E> {
+    key.parse::<i64>()
+        .map_err(|_| E::custom(format!("invalid id: {}", key)))
+}
+
+/// Parses the map key of a node or group and checks it matches the id inside
+fn check_key<E: de::Error>(key: &str, id: i64) -> Result<i64, E> {
+    match parse_key(key)? {
+        parsed if parsed == id => Ok(id),
+        _ => Err(E::custom(format!("key `{}` doesn't match id {}", key, id))),
+    }
+}
+
+fn deserialize_nodes<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<i64, Node>, D::Error> {
+    HashMap::<String, Node>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(k, v)| Ok((check_key(&k, v.id)?, v)))
+        .collect()
+}
+
+fn deserialize_groups<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<(Groups, GroupLayout), D::Error> {
+    // the group plugin has exported both a map keyed by id and a plain list
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawGroups {
+        Map(HashMap<String, Group>),
+        List(Vec<Group>),
+    }
+    match Option::<RawGroups>::deserialize(deserializer)? {
+        None => Ok(Default::default()),
+        Some(RawGroups::List(list)) => Ok((
+            list.into_iter().map(|g| (g.id, g)).collect(),
+            GroupLayout::List,
+        )),
+        Some(RawGroups::Map(map)) => Ok((
+            map.into_iter()
+                .map(|(k, v)| Ok((check_key(&k, v.id)?, v)))
+                .collect::<Result<_, D::Error>>()?,
+            GroupLayout::Map,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_builder_mirrors_connections() {
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let b = builder.add_node("Add", HashMap::new());
+        builder
+            .connect(a, "num", b, "num")
+            .unwrap()
+            .connect(a, "num", b, "num2")
+            .unwrap()
+            .connect(a, "num", b, "num")
+            .unwrap();
+        assert!(builder.connect(a, "num", 99, "num").is_err());
+
+        let graph = builder.build();
+        assert_eq!(graph.id, "demo@0.1.0");
+        assert_eq!(graph.nodes[&a].outputs["num"].connections.len(), 2);
+        assert_eq!(graph.nodes[&b].inputs["num"].connections[0].node, a);
+        assert_eq!(graph.nodes[&b].inputs["num2"].connections[0].output, "num");
+    }
+
+    fn chain() -> Graph {
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::new());
+        let b = builder.add_node("Add", HashMap::new());
+        let c = builder.add_node("Add", HashMap::new());
+        builder
+            .connect(a, "num", b, "num")
+            .unwrap()
+            .connect(b, "num", c, "num")
+            .unwrap()
+            .connect(a, "num", c, "num2")
+            .unwrap();
+        builder.build()
+    }
+
+    #[test]
+    fn test_mutations_keep_both_sides() {
+        let mut graph = chain();
+
+        graph.disconnect(1, "num", 3, "num2").unwrap();
+        assert!(graph.nodes[&3].inputs["num2"].connections.is_empty());
+        assert_eq!(graph.nodes[&1].outputs["num"].connections.len(), 1);
+        assert!(graph.disconnect(1, "num", 3, "num2").is_err());
+
+        graph
+            .rewire(
+                &Connection::new(1, "num", 2, "num"),
+                &Connection::new(1, "num", 2, "num2"),
+            )
+            .unwrap();
+        assert_eq!(
+            graph.connections(),
+            vec![
+                Connection::new(1, "num", 2, "num2"),
+                Connection::new(2, "num", 3, "num")
+            ]
+        );
+
+        graph.rename_output(2, "num", "sum").unwrap();
+        graph.rename_input(3, "num", "value").unwrap();
+        assert_eq!(graph.nodes[&3].inputs["value"].connections[0].output, "sum");
+        assert_eq!(graph.nodes[&2].outputs["sum"].connections[0].input, "value");
+
+        graph.remove_node(2).unwrap();
+        assert!(graph.connections().is_empty());
+        assert!(graph.nodes[&3].inputs["value"].connections.is_empty());
+    }
+
+    #[test]
+    fn test_merge_remaps_ids() {
+        let mut graph = chain();
+        let map = graph.merge(chain());
+        assert_eq!(map[&1], 4);
+        assert_eq!(graph.nodes.len(), 6);
+        assert!(graph
+            .connections()
+            .contains(&Connection::new(4, "num", 6, "num2")));
+        assert_eq!(graph.nodes[&6].inputs["num"].connections[0].node, 5);
+
+        // node 1 is left out, so the connections to it are dangling
+        let mut other = chain();
+        other.nodes.remove(&1);
+        let map = graph.merge(other);
+        assert_eq!(map[&2], 7);
+        assert!(graph.nodes[&7].inputs["num"].connections.is_empty());
+        assert!(graph.nodes[&8].inputs["num2"].connections.is_empty());
+        assert!(graph.nodes[&1].outputs["num"]
+            .connections
+            .iter()
+            .all(|c| c.node <= 3));
+
+        // a hand-built graph whose key and id disagree
+        let mut other = chain();
+        let node = other.nodes.remove(&3).unwrap();
+        other.nodes.insert(30, node);
+        let map = graph.merge(other);
+        assert_eq!(graph.nodes[&map[&30]].id, map[&30]);
+
+        let document = json!({
+            "id": "demo@0.1.0",
+            "nodes": { "1": { "id": 2, "name": "Number" } }
+        });
+        let error = serde_json::from_value::<Graph>(document).unwrap_err();
+        assert!(error.to_string().contains("doesn't match id 2"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let document = json!({
+            "id": "demo@0.1.0",
+            "nodes": {
+                "1": {
+                    "id": 1,
+                    "data": { "num": 2 },
+                    "inputs": {},
+                    "outputs": {
+                        "num": { "connections": [{ "node": 2, "input": "num", "data": {} }] }
+                    },
+                    "position": [10.5, 20.5],
+                    "name": "Number"
+                },
+                "2": {
+                    "id": 2,
+                    "data": {},
+                    "inputs": {
+                        "num": { "connections": [{ "node": 1, "output": "num", "data": { "pins": [] } }] }
+                    },
+                    "outputs": {},
+                    "position": [300.5, 20.5],
+                    "name": "Log"
+                }
+            },
+            "groups": {
+                "1": {
+                    "id": 1,
+                    "title": "Input",
+                    "nodes": [1],
+                    "minWidth": 200.0,
+                    "minHeight": 100.0,
+                    "position": [0.5, 0.5],
+                    "width": 250.0,
+                    "height": 120.0
+                }
+            },
+            "comments": [{ "text": "start here", "position": [5.5, 5.5], "links": [1] }],
+            "editor": { "zoom": 1.5 }
+        });
+
+        let graph: Graph = serde_json::from_value(document.clone()).unwrap();
+        assert_eq!(graph.id, "demo@0.1.0");
+        assert_eq!(graph.nodes[&2].inputs["num"].connections[0].node, 1);
+        assert_eq!(graph.groups[&1].nodes, vec![1]);
+        assert_eq!(graph.extra["editor"], json!({ "zoom": 1.5 }));
+
+        assert_eq!(serde_json::to_value(&graph).unwrap(), document);
+    }
+
+    #[test]
+    fn test_round_trip_keeps_layout() {
+        let document = json!({
+            "id": "demo@0.1.0",
+            "nodes": {
+                "1": {
+                    "id": 1,
+                    "data": {},
+                    "inputs": {},
+                    "outputs": {},
+                    "position": [10.1, 20.3],
+                    "name": "Number",
+                    "meta": { "collapsed": true }
+                }
+            },
+            "groups": [{
+                "id": 1,
+                "title": null,
+                "nodes": [1],
+                "minWidth": 0.1,
+                "minHeight": 0.2,
+                "position": [-7.3, 1.7],
+                "width": 250.3,
+                "height": 120.9,
+                "color": "#ff0000"
+            }]
+        });
+
+        let graph: Graph = serde_json::from_value(document.clone()).unwrap();
+        assert_eq!(graph.group_layout, GroupLayout::List);
+        assert_eq!(graph.nodes[&1].position, Some(vec![10.1, 20.3]));
+        assert_eq!(graph.nodes[&1].extra["meta"], json!({ "collapsed": true }));
+        assert_eq!(graph.groups[&1].extra["color"], json!("#ff0000"));
+        assert_eq!(serde_json::to_value(&graph).unwrap(), document);
+
+        let mut document = document;
+        document["comments"] = json!([]);
+        let graph: Graph = serde_json::from_value(document.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&graph).unwrap(), document);
+    }
+}
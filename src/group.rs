@@ -11,17 +11,45 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::collections::HashMap;
 
-#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
 pub struct Group {
-    id: i64,
-    nodes: Vec<i64>,
-    min_width: f32,
-    max_widht: f32,
-    position: [f32; 2],
-    width: f32,
-    height: f32,
+    pub id: i64,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub nodes: Vec<i64>,
+    #[serde(default)]
+    pub min_width: f64,
+    #[serde(default)]
+    pub min_height: f64,
+    #[serde(default)]
+    pub position: [f64; 2],
+    #[serde(default)]
+    pub width: f64,
+    #[serde(default)]
+    pub height: f64,
+    /// Fields the engine doesn't use, kept so the editor gets them back
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
-#[allow(dead_code)]
+
+impl Group {
+    pub fn contains(&self, node_id: i64) -> bool {
+        self.nodes.contains(&node_id)
+    }
+}
+
 pub type Groups = HashMap<i64, Group>;
+
+/// Whether a document lists its groups in a map keyed by id or in a plain list
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GroupLayout {
+    #[default]
+    Map,
+    List,
+}
@@ -0,0 +1,250 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::node::*;
+use crate::state::{StateKey, StateStore};
+use crate::CancellationToken;
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::time::Instant;
+
+/// Something a worker reported during a run
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RunEvent {
+    pub node_id: NodeId,
+    pub kind: String,
+    pub data: Value,
+}
+
+/// State shared by every worker of one run: values keyed by their type, and
+/// the events workers emitted. A fresh one is made for each run.
+#[derive(Default)]
+pub struct RunState {
+    extensions: RefCell<HashMap<TypeId, Box<dyn Any>>>,
+    events: RefCell<Vec<RunEvent>>,
+}
+
+impl RunState {
+    /// Stores `value`, returning the previous value of the same type
+    pub fn insert<T: 'static>(&self, value: T) -> Option<T> {
+        self.extensions
+            .borrow_mut()
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok().map(|old| *old))
+    }
+
+    pub fn get<T: Clone + 'static>(&self) -> Option<T> {
+        self.extensions
+            .borrow()
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+            .cloned()
+    }
+
+    pub fn remove<T: 'static>(&self) -> Option<T> {
+        self.extensions
+            .borrow_mut()
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok().map(|value| *value))
+    }
+
+    /// Calls `f` with the stored `T`, storing `T::default()` first if there is none
+    pub fn update<T: Default + 'static, R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        let mut extensions = self.extensions.borrow_mut();
+        let value = extensions
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(T::default()));
+        f(value.downcast_mut::<T>().unwrap())
+    }
+
+    pub fn emit(&self, event: RunEvent) {
+        self.events.borrow_mut().push(event)
+    }
+
+    /// Events in the order they were emitted
+    pub fn events(&self) -> Vec<RunEvent> {
+        self.events.borrow().clone()
+    }
+}
+
+impl Debug for RunState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RunState")
+            .field("extensions", &self.extensions.borrow().len())
+            .field("events", &self.events.borrow())
+            .finish()
+    }
+}
+
+/// What a worker is called with besides the node and its inputs
+pub struct Invocation<'a, TContext> {
+    context: &'a TContext,
+    state: &'a RunState,
+    node_id: NodeId,
+    /// Graph key and store of the persistent node state
+    store: Option<(&'a str, &'a dyn StateStore)>,
+    token: Option<&'a CancellationToken>,
+    deadline: Option<Instant>,
+}
+
+impl<'a, TContext> Invocation<'a, TContext> {
+    pub fn new(context: &'a TContext, state: &'a RunState, node_id: NodeId) -> Self {
+        Self {
+            context,
+            state,
+            node_id,
+            store: None,
+            token: None,
+            deadline: None,
+        }
+    }
+
+    /// Lets the worker keep state in `store` under `graph_key` and its node id
+    pub fn with_store(mut self, graph_key: &'a str, store: &'a dyn StateStore) -> Self {
+        self.store = Some((graph_key, store));
+        self
+    }
+
+    /// Lets the worker see when the run is cancelled by `token`
+    pub fn with_token(mut self, token: &'a CancellationToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Lets the worker see when the run times out
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn context(&self) -> &'a TContext {
+        self.context
+    }
+
+    pub fn state(&self) -> &'a RunState {
+        self.state
+    }
+
+    /// The same invocation with the context a layer passed on
+    pub(crate) fn with_context<'b>(&self, context: &'b TContext) -> Invocation<'b, TContext>
+    where
+        'a: 'b,
+    {
+        Invocation {
+            context,
+            state: self.state,
+            node_id: self.node_id,
+            store: self.store,
+            token: self.token,
+            deadline: self.deadline,
+        }
+    }
+
+    /// The token of a cancellable run, for long running workers to check or
+    /// hand on to work they start
+    pub fn cancellation(&self) -> Option<&'a CancellationToken> {
+        self.token
+    }
+
+    /// True once the run this worker belongs to is cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_some_and(|t| t.is_cancelled())
+    }
+
+    /// When the run times out, see `EngineOptions::run_timeout`
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// True once the run is past its deadline
+    pub fn is_past_deadline(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() > deadline)
+    }
+
+    /// Adds an event from the node being run to the run state
+    pub fn emit(&self, kind: &str, data: Value) {
+        self.state.emit(RunEvent {
+            node_id: self.node_id,
+            kind: kind.to_string(),
+            data,
+        })
+    }
+
+    /// The state this node stored in an earlier run
+    pub fn node_state<T: DeserializeOwned>(&self) -> Result<Option<T>> {
+        let (store, key) = self.state_key()?;
+        match store.get(&key)? {
+            Some(value) => Ok(Some(serde_json::from_value(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_node_state<T: Serialize>(&self, value: &T) -> Result<()> {
+        let (store, key) = self.state_key()?;
+        store.put(key, serde_json::to_value(value)?)
+    }
+
+    pub fn clear_node_state(&self) -> Result<()> {
+        let (store, key) = self.state_key()?;
+        store.remove(&key)
+    }
+
+    /// Calls `f` with the state of this node, starting from `T::default()` if
+    /// there is none, and stores the result
+    pub fn update_node_state<T, R, F>(&self, f: F) -> Result<R>
+    where
+        T: Serialize + DeserializeOwned + Default,
+        F: FnOnce(&mut T) -> R,
+    {
+        let mut value = self.node_state::<T>()?.unwrap_or_default();
+        let result = f(&mut value);
+        self.set_node_state(&value)?;
+        Ok(result)
+    }
+
+    fn state_key(&self) -> Result<(&'a dyn StateStore, StateKey)> {
+        match self.store {
+            Some((graph_key, store)) => Ok((store, StateKey::new(graph_key, self.node_id))),
+            None => bail!(
+                "node {} has no persistent state, see `RunOptions::graph_key`",
+                self.node_id
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extensions() {
+        let state = RunState::default();
+        assert_eq!(state.get::<u32>(), None);
+        assert_eq!(state.insert(1u32), None);
+        assert_eq!(state.insert(2u32), Some(1));
+        state.update(|count: &mut u32| *count += 1);
+        state.update(|names: &mut Vec<String>| names.push("a".to_string()));
+        assert_eq!(state.get::<u32>(), Some(3));
+        assert_eq!(state.remove::<Vec<String>>(), Some(vec!["a".to_string()]));
+        assert_eq!(state.get::<Vec<String>>(), None);
+    }
+}
@@ -0,0 +1,192 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::invocation::{Invocation, RunState};
+use crate::node::*;
+use crate::schema::*;
+use crate::workers::Worker;
+use anyhow::Result;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Wraps every worker call. A layer gets the node and its inputs, and either
+/// passes them on through `next` or answers itself without calling the worker.
+pub trait Layer<TContext> {
+    fn call(
+        &self,
+        context: &TContext,
+        node: &Node,
+        input_data: HashMap<String, OutputValue>,
+        next: Next<'_, TContext>,
+    ) -> Result<HashMap<String, OutputValue>>;
+}
+
+/// The rest of the layers and the worker they wrap
+pub struct Next<'a, TContext> {
+    worker: &'a dyn Worker<TContext>,
+    layers: &'a [Box<dyn Layer<TContext>>],
+    invocation: &'a Invocation<'a, TContext>,
+}
+
+impl<'a, TContext> Next<'a, TContext> {
+    pub(crate) fn new(
+        worker: &'a dyn Worker<TContext>,
+        layers: &'a [Box<dyn Layer<TContext>>],
+        invocation: &'a Invocation<'a, TContext>,
+    ) -> Self {
+        Self {
+            worker,
+            layers,
+            invocation,
+        }
+    }
+
+    /// The run state the worker will be invoked with
+    pub fn state(&self) -> &'a RunState {
+        self.invocation.state()
+    }
+
+    /// The worker at the end of the chain
+    pub fn worker(&self) -> &'a dyn Worker<TContext> {
+        self.worker
+    }
+
+    pub fn run(
+        self,
+        context: &TContext,
+        node: &Node,
+        input_data: HashMap<String, OutputValue>,
+    ) -> Result<HashMap<String, OutputValue>> {
+        match self.layers.split_first() {
+            Some((layer, layers)) => {
+                let next = Next::new(self.worker, layers, self.invocation);
+                layer.call(context, node, input_data, next)
+            }
+            None => self
+                .worker
+                .invoke(&self.invocation.with_context(context), node, input_data),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WorkerTiming {
+    pub calls: u64,
+    pub total: Duration,
+}
+
+/// Adds up how long each worker takes. Clones share the totals, so keep one to
+/// read them after adding another to the builder.
+#[derive(Clone, Default)]
+pub struct TimingLayer(Rc<RefCell<HashMap<String, WorkerTiming>>>);
+
+impl TimingLayer {
+    pub fn timings(&self) -> HashMap<String, WorkerTiming> {
+        self.0.borrow().clone()
+    }
+}
+
+impl<TContext> Layer<TContext> for TimingLayer {
+    fn call(
+        &self,
+        context: &TContext,
+        node: &Node,
+        input_data: HashMap<String, OutputValue>,
+        next: Next<'_, TContext>,
+    ) -> Result<HashMap<String, OutputValue>> {
+        let started = Instant::now();
+        let result = next.run(context, node, input_data);
+        let mut timings = self.0.borrow_mut();
+        let timing = timings.entry(node.name.clone()).or_default();
+        timing.calls += 1;
+        timing.total += started.elapsed();
+        result
+    }
+}
+
+/// Checks inputs before and outputs after each call against the worker's schema
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ValidationLayer;
+
+impl<TContext> Layer<TContext> for ValidationLayer {
+    fn call(
+        &self,
+        context: &TContext,
+        node: &Node,
+        input_data: HashMap<String, OutputValue>,
+        next: Next<'_, TContext>,
+    ) -> Result<HashMap<String, OutputValue>> {
+        let schema = next.worker().schema();
+        if !schema.is_declared() {
+            return next.run(context, node, input_data);
+        }
+        for port in &schema.inputs {
+            match input_data.get(&port.name) {
+                None if port.required => bail!(SchemaError::MissingInput {
+                    node_id: node.id,
+                    port: port.name.clone(),
+                }),
+                Some(value) if !port.socket.accepts(value) => {
+                    bail!(SchemaError::InputTypeMismatch {
+                        node_id: node.id,
+                        port: port.name.clone(),
+                        expected: port.socket,
+                        actual: value.to_string(),
+                    })
+                }
+                _ => (),
+            }
+        }
+        let output = next.run(context, node, input_data)?;
+        if let Some(name) = output.keys().find(|name| schema.get_output(name).is_none()) {
+            bail!(SchemaError::UnknownOutput {
+                node_id: node.id,
+                port: name.clone(),
+            });
+        }
+        // the same check as `EngineOptions::check_output_types`, with the same error
+        schema.check_outputs(node.id, &output)?;
+        Ok(output)
+    }
+}
+
+/// Logs every call at `debug` and failures at `warn` through the `log` crate
+#[cfg(feature = "log")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LoggingLayer;
+
+#[cfg(feature = "log")]
+impl<TContext> Layer<TContext> for LoggingLayer {
+    fn call(
+        &self,
+        context: &TContext,
+        node: &Node,
+        input_data: HashMap<String, OutputValue>,
+        next: Next<'_, TContext>,
+    ) -> Result<HashMap<String, OutputValue>> {
+        log::debug!(
+            "calling `{}` for node {} with {:?}",
+            node.name,
+            node.id,
+            input_data
+        );
+        let result = next.run(context, node, input_data);
+        match &result {
+            Ok(output) => log::debug!("`{}` returned {:?}", node.name, output),
+            Err(e) => log::warn!("`{}` failed for node {}: {}", node.name, node.id, e),
+        }
+        result
+    }
+}
@@ -14,26 +14,70 @@
 #[macro_use]
 extern crate anyhow;
 
+mod cancel;
+mod catalog;
+mod debug;
+mod execution;
+mod graph;
 mod group;
+mod invocation;
+mod layer;
+mod memo;
+mod normalize;
+mod observer;
+mod retry;
 mod target;
+mod trace;
+mod version;
 #[macro_use]
 mod node;
 mod engine;
+mod schema;
+mod session;
+mod state;
 mod workers;
 
+pub use cancel::*;
+pub use catalog::*;
+pub use debug::*;
 pub use engine::*;
+pub use graph::*;
 pub use group::*;
+pub use invocation::*;
+pub use layer::*;
+pub use memo::*;
 pub use node::*;
+pub use normalize::*;
+pub use observer::*;
+pub use retry::*;
+pub use schema::*;
+pub use session::*;
+pub use state::*;
 pub use target::*;
+pub use trace::*;
+pub use version::*;
 pub use workers::*;
 
 #[cfg(test)]
 mod tests {
-    use crate::engine::Engine;
+    use crate::engine::{Engine, EngineError, EngineOptions, ErrorPolicy, Normalize, RunOptions};
     use crate::workers::WorkersBuilder;
-    use crate::{node::*, Worker};
+    use crate::{node::*, Connection, GraphBuilder, Migrations, Worker, WorkerError};
+    use crate::{Backoff, Clock, Retries, RetryPolicy};
+    use crate::{
+        Breakpoint, CancellationToken, DebugEvent, ExecutionObserver, NodeStatus, SkipReason,
+        TraceRecorder,
+    };
+    use crate::{ComponentSchema, ControlSchema, ControlType, PortSchema, SchemaError};
+    use crate::{FileStateStore, MemoryStateStore, StateKey};
+    use crate::{Invocation, Layer, Next, TimingLayer, ValidationLayer};
+    use crate::{Limits, LruMemoStore, Memo, MemoStats, SocketRules, SocketType};
     use anyhow::Result;
+    use serde_json::json;
+    use std::cell::{Cell, RefCell};
     use std::collections::HashMap;
+    use std::rc::Rc;
+    use std::time::Duration;
 
     #[test]
     fn multiply_works() {
@@ -508,12 +552,1862 @@ mod tests {
         assert!(output.err().is_some());
     }
 
+    #[test]
+    fn groups_work() {
+        let json_data = r#"
+    {
+      "id": "demo@0.1.0",
+      "nodes": {
+        "1": {
+          "id": 1,
+          "data": { "num": 2 },
+          "inputs": {},
+          "outputs": {
+            "num": {
+              "connections": [
+                { "node": 3, "input": "num", "data": {} },
+                { "node": 4, "input": "num2", "data": {} }
+              ]
+            }
+          },
+          "position": [0, 0],
+          "name": "Number"
+        },
+        "2": {
+          "id": 2,
+          "data": { "num": 3 },
+          "inputs": {},
+          "outputs": {
+            "num": { "connections": [{ "node": 3, "input": "num2", "data": {} }] }
+          },
+          "position": [0, 200],
+          "name": "Number"
+        },
+        "3": {
+          "id": 3,
+          "data": {},
+          "inputs": {
+            "num": { "connections": [{ "node": 1, "output": "num", "data": {} }] },
+            "num2": { "connections": [{ "node": 2, "output": "num", "data": {} }] }
+          },
+          "outputs": {
+            "num": { "connections": [{ "node": 4, "input": "num", "data": {} }] }
+          },
+          "position": [300, 100],
+          "name": "Add"
+        },
+        "4": {
+          "id": 4,
+          "data": {},
+          "inputs": {
+            "num": { "connections": [{ "node": 3, "output": "num", "data": {} }] },
+            "num2": { "connections": [{ "node": 1, "output": "num", "data": {} }] }
+          },
+          "outputs": {
+            "num": { "connections": [] }
+          },
+          "position": [600, 100],
+          "name": "Add"
+        }
+      },
+      "groups": {
+        "1": {
+          "id": 1,
+          "title": "Sum",
+          "nodes": [1, 2, 3],
+          "minWidth": 400,
+          "minHeight": 200,
+          "position": [-20, -20],
+          "width": 500,
+          "height": 400
+        }
+      },
+      "comments": []
+    }
+    "#;
+
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Add);
+
+        let engine = Engine::new("demo@0.1.0".to_string(), workers.build());
+        let graph = engine.parse_graph_json(json_data).unwrap();
+        assert_eq!(graph.groups[&1].title.as_deref(), Some("Sum"));
+        let ids: Vec<i64> = graph.nodes_in_group(1).iter().map(|n| n.id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(graph.group_of(3).map(|g| g.id), Some(1));
+        assert!(graph.group_of(4).is_none());
+
+        let output = engine.process_group(&(), &graph, 1, 1).unwrap();
+        assert_eq!(output["num"], OutputValue::I64(5i64));
+    }
+
+    #[test]
+    fn builder_graph_works() {
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let b = builder.add_node("Number", HashMap::from([("num".to_string(), json!(5))]));
+        let add = builder.add_node("Add", HashMap::new());
+        builder
+            .connect(a, "num", add, "num")
+            .unwrap()
+            .connect(b, "num", add, "num2")
+            .unwrap();
+        let nodes = builder.build_nodes();
+
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Add);
+
+        let engine = Engine::new("demo@0.1.0".to_string(), workers.build());
+        let output = engine.process(&(), &nodes, a).unwrap();
+        assert_eq!(output["num"], OutputValue::I64(7i64));
+    }
+
+    #[test]
+    fn normalize_repairs_one_sided_connections() {
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let b = builder.add_node("Number", HashMap::from([("num".to_string(), json!(5))]));
+        let add = builder.add_node("Add", HashMap::new());
+        builder
+            .connect(a, "num", add, "num")
+            .unwrap()
+            .connect(b, "num", add, "num2")
+            .unwrap();
+        let mut graph = builder.build();
+        // only keep the output side, like a hand edited file
+        graph.nodes.get_mut(&add).unwrap().inputs.clear();
+        let value = serde_json::to_value(&graph).unwrap();
+
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Add);
+
+        let options = EngineOptions {
+            normalize: Normalize::Repair,
+            ..Default::default()
+        };
+        let engine = Engine::with_options("demo@0.1.0".to_string(), workers.build(), options);
+        let (graph, report) = engine.parse_graph_value_with_report(value).unwrap();
+        assert_eq!(
+            report.repaired,
+            vec![
+                Connection::new(a, "num", add, "num"),
+                Connection::new(b, "num", add, "num2")
+            ]
+        );
+        let output = engine.process(&(), &graph.nodes, a).unwrap();
+        assert_eq!(output["num"], OutputValue::I64(7i64));
+    }
+
+    #[test]
+    fn versions_and_migrations_work() {
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Num", HashMap::from([("value".to_string(), json!(2))]));
+        let b = builder.add_node("Num", HashMap::from([("value".to_string(), json!(5))]));
+        let add = builder.add_node("Add", HashMap::new());
+        builder
+            .connect(a, "value", add, "num")
+            .unwrap()
+            .connect(b, "value", add, "num2")
+            .unwrap();
+        let value = serde_json::to_value(builder.build()).unwrap();
+
+        let build_engine = |id: &str| {
+            let mut workers = WorkersBuilder::default();
+            workers.add(Number);
+            workers.add(Add);
+            Engine::new(id.to_string(), workers.build())
+        };
+
+        // a patch release still accepts the graph, another major does not
+        assert!(build_engine("demo@0.1.1")
+            .parse_value(value.clone())
+            .is_ok());
+        assert!(build_engine("demo@0.2.0")
+            .parse_value(value.clone())
+            .is_err());
+        assert!(build_engine("other@0.1.0")
+            .parse_value(value.clone())
+            .is_err());
+        // an older engine doesn't load a graph saved by a newer one
+        let newer = {
+            let mut value = value.clone();
+            value["id"] = json!("demo@0.1.9");
+            value
+        };
+        assert!(matches!(
+            build_engine("demo@0.1.0").parse_value(newer),
+            Err(EngineError::VersionMismatch(..))
+        ));
+
+        let mut migrations = Migrations::default();
+        migrations
+            .add("0.1.0", "0.2.0", |graph| {
+                let ids: Vec<i64> = graph.nodes.keys().copied().collect();
+                for id in ids {
+                    if graph.nodes[&id].name == "Num" {
+                        graph.rename_output(id, "value", "num")?;
+                        let node = graph.nodes.get_mut(&id).unwrap();
+                        node.name = "Number".to_string();
+                        let value = node.data.remove("value").unwrap_or_default();
+                        node.data.insert("num".to_string(), value);
+                    }
+                }
+                Ok(())
+            })
+            .unwrap();
+        let mut engine = build_engine("demo@0.2.0");
+        engine.set_migrations(migrations);
+        let graph = engine.parse_graph_value(value).unwrap();
+        assert_eq!(graph.id, "demo@0.2.0");
+        let output = engine.process(&(), &graph.nodes, a).unwrap();
+        assert_eq!(output["num"], OutputValue::I64(7i64));
+    }
+
+    #[test]
+    fn worker_versions_work() {
+        struct NumberV2;
+        impl Worker<()> for NumberV2 {
+            fn name(&self) -> &str {
+                "Number"
+            }
+
+            fn version(&self) -> u32 {
+                2
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                node: &Node,
+                _input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                let result: i64 = node.get_data("value")?.unwrap();
+                let mut h = HashMap::new();
+                h.insert("num".to_string(), OutputValue::I64(result));
+                Ok(h)
+            }
+        }
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node(
+            "Number",
+            HashMap::from([
+                ("num".to_string(), json!(2)),
+                ("_version".to_string(), json!(1)),
+            ]),
+        );
+        let b = builder.add_node("Number", HashMap::from([("value".to_string(), json!(5))]));
+        let add = builder.add_node("Add", HashMap::new());
+        builder
+            .connect(a, "num", add, "num")
+            .unwrap()
+            .connect(b, "num", add, "num2")
+            .unwrap();
+        let mut nodes = builder.build_nodes();
+
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(NumberV2);
+        workers.add(Add);
+        workers.migration("Number", 1, 2, |data| {
+            let num = data.remove("num").unwrap_or_default();
+            data.insert("value".to_string(), num);
+            Ok(())
+        });
+        let workers = workers.build();
+
+        let output = workers.call("Number", &(), &nodes[&a], HashMap::new());
+        assert_eq!(output.unwrap()["num"], OutputValue::I64(2i64));
+
+        // saved before versioning, so it is migrated from version 1
+        let mut legacy = nodes[&a].clone();
+        legacy.data.remove("_version");
+        let output = workers.call("Number", &(), &legacy, HashMap::new());
+        assert_eq!(output.unwrap()["num"], OutputValue::I64(2i64));
+
+        nodes.get_mut(&b).unwrap().set_version(3);
+        assert!(workers
+            .call("Number", &(), &nodes[&b], HashMap::new())
+            .is_err());
+        nodes.get_mut(&b).unwrap().set_version(2);
+
+        let engine = Engine::new("demo@0.1.0".to_string(), workers);
+        let output = engine.process(&(), &nodes, a).unwrap();
+        assert_eq!(output["num"], OutputValue::I64(7i64));
+    }
+
+    #[test]
+    fn schema_validation_works() {
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let add = builder.add_node("Add", HashMap::new());
+        let log = builder.add_node("Log", HashMap::new());
+        builder
+            .connect(a, "num", add, "num")
+            .unwrap()
+            .connect(add, "num", log, "num")
+            .unwrap();
+        let nodes = builder.build_nodes();
+
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Add);
+
+        assert_eq!(
+            workers.build().validate(&nodes),
+            vec![
+                SchemaError::MissingInput {
+                    node_id: add,
+                    port: "num2".to_string()
+                },
+                SchemaError::UnknownComponent {
+                    node_id: log,
+                    name: "Log".to_string()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn socket_types_are_checked() {
+        struct Text(SocketType);
+        impl Worker<()> for Text {
+            fn name(&self) -> &str {
+                "Text"
+            }
+
+            fn schema(&self) -> ComponentSchema {
+                ComponentSchema::new("Text").output(PortSchema::new("num", self.0))
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                _node: &Node,
+                _input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                let mut h = HashMap::new();
+                h.insert("num".to_string(), OutputValue::String("2".to_string()));
+                Ok(h)
+            }
+        }
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Text", HashMap::new());
+        let b = builder.add_node("Number", HashMap::from([("num".to_string(), json!(5))]));
+        let add = builder.add_node("Add", HashMap::new());
+        builder
+            .connect(a, "num", add, "num")
+            .unwrap()
+            .connect(b, "num", add, "num2")
+            .unwrap();
+        let nodes = builder.build_nodes();
+
+        let build_engine = |text: Text, options: EngineOptions| {
+            let mut workers = WorkersBuilder::default();
+            workers.add(text);
+            workers.add(Number);
+            workers.add(Add);
+            Engine::with_options("demo@0.1.0".to_string(), workers.build(), options)
+        };
+
+        let engine = build_engine(Text(SocketType::String), EngineOptions::default());
+        assert_eq!(
+            engine.check_types(&nodes),
+            vec![SchemaError::SocketMismatch {
+                connection: Connection::new(a, "num", add, "num"),
+                output: SocketType::String,
+                input: SocketType::I64
+            }]
+        );
+        let options = EngineOptions {
+            socket_rules: SocketRules::default().allow(SocketType::String, SocketType::I64),
+            ..Default::default()
+        };
+        let engine = build_engine(Text(SocketType::String), options);
+        assert!(engine.check_types(&nodes).is_empty());
+
+        // a schema that doesn't match what the worker produces is caught at run time
+        let options = EngineOptions {
+            check_output_types: true,
+            ..Default::default()
+        };
+        let engine = build_engine(Text(SocketType::I64), options);
+        assert!(engine.check_types(&nodes).is_empty());
+        let error = engine.process(&(), &nodes, a).unwrap_err();
+        assert!(matches!(
+            error,
+            EngineError::OutputTypeMismatch { node_id: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn catalog_export_works() {
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Multiply);
+        workers.add(Add);
+
+        let catalog = workers.build().catalog();
+        let names: Vec<&str> = catalog.components.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["Add", "Multiply", "Number"]);
+        assert_eq!(catalog.get("Number").unwrap().version, Some(1));
+
+        let json: serde_json::Value = serde_json::from_str(&catalog.to_json().unwrap()).unwrap();
+        assert_eq!(json["components"][0]["inputs"][1]["socket"], json!("i64"));
+        assert!(catalog
+            .to_typescript()
+            .contains("export interface NumberData {\n  num: number;\n}"));
+    }
+
+    #[test]
+    fn session_recomputes_changed_nodes() {
+        // records which nodes ran
+        type Log = RefCell<Vec<i64>>;
+        struct LoggedNumber;
+        impl Worker<Log> for LoggedNumber {
+            fn name(&self) -> &str {
+                "Number"
+            }
+
+            fn work(
+                &self,
+                context: &Log,
+                node: &Node,
+                _input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                context.borrow_mut().push(node.id);
+                Number.work(&(), node, HashMap::new())
+            }
+        }
+        struct LoggedAdd;
+        impl Worker<Log> for LoggedAdd {
+            fn name(&self) -> &str {
+                "Add"
+            }
+
+            fn work(
+                &self,
+                context: &Log,
+                node: &Node,
+                input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                context.borrow_mut().push(node.id);
+                Add.work(&(), node, input_data)
+            }
+        }
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let b = builder.add_node("Number", HashMap::from([("num".to_string(), json!(5))]));
+        let c = builder.add_node("Number", HashMap::from([("num".to_string(), json!(1))]));
+        let add = builder.add_node("Add", HashMap::new());
+        let add2 = builder.add_node("Add", HashMap::new());
+        builder
+            .connect(a, "num", add, "num")
+            .unwrap()
+            .connect(b, "num", add, "num2")
+            .unwrap()
+            .connect(add, "num", add2, "num")
+            .unwrap()
+            .connect(c, "num", add2, "num2")
+            .unwrap();
+
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(LoggedNumber);
+        workers.add(LoggedAdd);
+
+        let engine = Engine::new("demo@0.1.0".to_string(), workers.build());
+        let mut session = engine.session(builder.build_nodes(), a);
+        let log = Log::default();
+        assert_eq!(session.run(&log).unwrap()["num"], OutputValue::I64(8i64));
+        assert_eq!(log.borrow().len(), 5);
+
+        log.borrow_mut().clear();
+        session.update_node_data(c, "num", json!(10)).unwrap();
+        assert!(session.cached(add2).is_none());
+        assert_eq!(session.run(&log).unwrap()["num"], OutputValue::I64(17i64));
+        assert_eq!(*log.borrow(), vec![c, add2]);
+
+        log.borrow_mut().clear();
+        session.disconnect(b, "num", add, "num2").unwrap();
+        session.connect(c, "num", add, "num2").unwrap();
+        assert_eq!(session.run(&log).unwrap()["num"], OutputValue::I64(22i64));
+        assert_eq!(*log.borrow(), vec![add, add2]);
+    }
+
+    #[test]
+    fn pure_workers_are_memoized() {
+        struct Square(Cell<usize>);
+        impl Worker<()> for Square {
+            fn name(&self) -> &str {
+                "Square"
+            }
+
+            fn pure(&self) -> bool {
+                true
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                _node: &Node,
+                input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                self.0.set(self.0.get() + 1);
+                let num = input_data["num"].as_i64()?;
+                let mut h = HashMap::new();
+                h.insert("num".to_string(), OutputValue::I64(num * num));
+                Ok(h)
+            }
+        }
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(3))]));
+        let square = builder.add_node("Square", HashMap::new());
+        builder.connect(a, "num", square, "num").unwrap();
+        let mut nodes = builder.build_nodes();
+
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Square(Cell::new(0)));
+
+        let mut engine = Engine::new("demo@0.1.0".to_string(), workers.build());
+        engine.set_memo(Memo::new(LruMemoStore::new(16)));
+        for _ in 0..3 {
+            let output = engine.process(&(), &nodes, a).unwrap();
+            assert_eq!(output["num"], OutputValue::I64(9i64));
+        }
+        assert_eq!(engine.memo_stats(), Some(MemoStats { hits: 2, misses: 1 }));
+
+        nodes
+            .get_mut(&a)
+            .unwrap()
+            .data
+            .insert("num".to_string(), json!(4));
+        let output = engine.process(&(), &nodes, a).unwrap();
+        assert_eq!(output["num"], OutputValue::I64(16i64));
+        assert_eq!(engine.memo_stats(), Some(MemoStats { hits: 2, misses: 2 }));
+    }
+
+    #[derive(Default)]
+    struct Recorder(RefCell<Vec<String>>);
+    impl ExecutionObserver for Recorder {
+        fn run_started(&self, start_node_id: i64) {
+            self.0.borrow_mut().push(format!("run {}", start_node_id));
+        }
+
+        fn run_finished(
+            &self,
+            _start_node_id: i64,
+            _duration: Duration,
+            error: Option<&EngineError>,
+        ) {
+            self.0
+                .borrow_mut()
+                .push(format!("done {}", error.is_none()));
+        }
+
+        fn node_started(&self, node: &Node, inputs: &HashMap<String, OutputValue>) {
+            self.0
+                .borrow_mut()
+                .push(format!("start {} {}", node.id, inputs.len()));
+        }
+
+        fn node_finished(
+            &self,
+            node: &Node,
+            _outputs: &HashMap<String, OutputValue>,
+            _duration: Duration,
+        ) {
+            self.0.borrow_mut().push(format!("end {}", node.id));
+        }
+
+        fn node_skipped(&self, node: &Node, reason: &SkipReason) {
+            self.0
+                .borrow_mut()
+                .push(format!("skip {} ({})", node.id, reason));
+        }
+
+        fn node_failed(&self, node: &Node, _error: &EngineError, _duration: Duration) {
+            self.0.borrow_mut().push(format!("fail {}", node.id));
+        }
+    }
+
+    #[test]
+    fn observers_see_the_run() {
+        struct Gate;
+        impl Worker<()> for Gate {
+            fn name(&self) -> &str {
+                "Gate"
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                _node: &Node,
+                _input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                Ok(HashMap::new())
+            }
+        }
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let b = builder.add_node("Number", HashMap::from([("num".to_string(), json!(5))]));
+        let add = builder.add_node("Add", HashMap::new());
+        let gate = builder.add_node("Gate", HashMap::new());
+        let gated = builder.add_node("Add", HashMap::new());
+        // only gated by itself, skipping it must not loop forever
+        let looped = builder.add_node("Add", HashMap::new());
+        builder
+            .connect(a, "num", add, "num")
+            .unwrap()
+            .connect(b, "num", add, "num2")
+            .unwrap()
+            .connect(add, "num", gate, "num")
+            .unwrap()
+            .connect(gate, "num", gated, "num")
+            .unwrap()
+            .connect(gate, "action", gated, "action")
+            .unwrap()
+            .connect(gate, "num", looped, "num")
+            .unwrap()
+            .connect(looped, "action", looped, "action")
+            .unwrap();
+        let nodes = builder.build_nodes();
+
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Add);
+        workers.add(Gate);
+
+        let mut engine = Engine::new("demo@0.1.0".to_string(), workers.build());
+        let recorder = Rc::new(Recorder::default());
+        engine.add_observer(recorder.clone());
+        engine.process(&(), &nodes, a).unwrap();
+        assert_eq!(
+            *recorder.0.borrow(),
+            vec![
+                "run 1",
+                "start 1 0",
+                "end 1",
+                "start 2 0",
+                "end 2",
+                "start 3 2",
+                "end 3",
+                "start 4 1",
+                "end 4",
+                "skip 5 (node 4 produced no `num`)",
+                "skip 6 (node 4 produced no `num`)",
+                "done true",
+            ]
+        );
+    }
+
+    #[test]
+    fn trace_export_works() {
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let b = builder.add_node("Number", HashMap::from([("num".to_string(), json!("abc"))]));
+        let add = builder.add_node("Add", HashMap::new());
+        let lonely = builder.add_node("Number", HashMap::from([("num".to_string(), json!(1))]));
+        builder
+            .connect(a, "num", add, "num")
+            .unwrap()
+            .connect(b, "num", add, "num2")
+            .unwrap();
+        let nodes = builder.build_nodes();
+
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Add);
+
+        let engine = Engine::new("demo@0.1.0".to_string(), workers.build());
+        let (result, trace) = engine.process_traced(&(), &nodes, a);
+        assert!(result.is_err());
+        assert_eq!(trace.order, vec![a, b]);
+        assert_eq!(trace.nodes[&a].status, NodeStatus::Ran);
+        assert_eq!(trace.nodes[&a].outputs["num"], OutputValue::I64(2));
+        assert_eq!(trace.nodes[&b].status, NodeStatus::Failed);
+        assert_eq!(trace.nodes[&add].status, NodeStatus::NeverReached);
+        assert_eq!(trace.nodes[&lonely].status, NodeStatus::NeverReached);
+        assert!(trace.error.is_some());
+
+        let json: serde_json::Value = serde_json::from_str(&trace.to_json().unwrap()).unwrap();
+        assert_eq!(json["startNodeId"], json!(1));
+        assert_eq!(json["nodes"]["1"]["status"], json!("ran"));
+        assert_eq!(json["nodes"]["1"]["outputs"]["num"], json!({ "I64": 2 }));
+        assert_eq!(json["nodes"]["2"]["status"], json!("failed"));
+        assert_eq!(json["nodes"]["4"]["status"], json!("neverReached"));
+    }
+
+    #[test]
+    fn debugger_pauses_at_breakpoints() {
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let b = builder.add_node("Number", HashMap::from([("num".to_string(), json!(5))]));
+        let add = builder.add_node("Add", HashMap::new());
+        let multiply = builder.add_node("Multiply", HashMap::new());
+        builder
+            .connect(a, "num", add, "num")
+            .unwrap()
+            .connect(b, "num", add, "num2")
+            .unwrap()
+            .connect(add, "num", multiply, "num")
+            .unwrap()
+            .connect(b, "num", multiply, "num2")
+            .unwrap();
+        let nodes = builder.build_nodes();
+
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Add);
+        workers.add(Multiply);
+
+        let engine = Engine::new("demo@0.1.0".to_string(), workers.build());
+        let mut debugger = engine.debug(&(), &nodes, a);
+        debugger.add_breakpoint(Breakpoint::Worker("Add".to_string()));
+        assert_eq!(debugger.resume().unwrap(), DebugEvent::Paused(add));
+        assert_eq!(debugger.pending_node().unwrap().id, add);
+        assert_eq!(
+            debugger.pending_inputs().unwrap(),
+            &HashMap::from([
+                ("num".to_string(), OutputValue::I64(2)),
+                ("num2".to_string(), OutputValue::I64(5)),
+            ])
+        );
+        assert_eq!(debugger.completed(), vec![a, b]);
+        assert_eq!(debugger.step().unwrap(), DebugEvent::Paused(multiply));
+        assert_eq!(debugger.outputs(add).unwrap()["num"], OutputValue::I64(7));
+        assert_eq!(
+            debugger.resume().unwrap(),
+            DebugEvent::Finished(HashMap::from([("num".to_string(), OutputValue::I64(35))]))
+        );
+
+        let mut debugger = engine.debug(&(), &nodes, a);
+        debugger.add_breakpoint(Breakpoint::Node(multiply));
+        assert_eq!(debugger.resume().unwrap(), DebugEvent::Paused(multiply));
+        let outputs = debugger.abort();
+        assert_eq!(outputs.len(), 3);
+        assert!(!outputs.contains_key(&multiply));
+    }
+
+    #[test]
+    fn cancellation_and_timeouts_work() {
+        struct Stop;
+        impl Worker<()> for Stop {
+            fn name(&self) -> &str {
+                "Stop"
+            }
+
+            fn invoke(
+                &self,
+                invocation: &Invocation<'_, ()>,
+                _node: &Node,
+                input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                if let Some(token) = invocation.cancellation() {
+                    assert!(!invocation.is_cancelled());
+                    token.cancel();
+                    assert!(invocation.is_cancelled());
+                }
+                Ok(input_data)
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                _node: &Node,
+                input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                Ok(input_data)
+            }
+        }
+
+        struct Slow;
+        impl Worker<()> for Slow {
+            fn name(&self) -> &str {
+                "Slow"
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                _node: &Node,
+                input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                std::thread::sleep(Duration::from_millis(5));
+                Ok(input_data)
+            }
+        }
+
+        let build = |name: &str| {
+            let mut builder = GraphBuilder::new("demo@0.1.0");
+            let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+            let b = builder.add_node(name, HashMap::new());
+            let add = builder.add_node("Add", HashMap::new());
+            builder
+                .connect(a, "num", b, "num")
+                .unwrap()
+                .connect(b, "num", add, "num")
+                .unwrap()
+                .connect(a, "num", add, "num2")
+                .unwrap();
+            (builder.build_nodes(), a, b, add)
+        };
+        let token = CancellationToken::new();
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Add);
+        workers.add(Stop);
+        workers.add(Slow);
+
+        let engine = Engine::new("demo@0.1.0".to_string(), workers.build());
+        let (nodes, a, stop, add) = build("Stop");
+        // without a token the worker has nothing to cancel
+        assert!(engine.process(&(), &nodes, a).is_ok());
+        let (result, outputs) = engine.process_cancellable(&(), &nodes, a, &token);
+        assert!(matches!(result.unwrap_err(), EngineError::Cancelled));
+        assert!(outputs.contains_key(&a));
+        assert!(outputs.contains_key(&stop));
+        assert!(!outputs.contains_key(&add));
+
+        let options = EngineOptions {
+            worker_timeouts: HashMap::from([("Slow".to_string(), Duration::from_millis(1))]),
+            ..Default::default()
+        };
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Add);
+        workers.add(Slow);
+
+        let engine = Engine::with_options("demo@0.1.0".to_string(), workers.build(), options);
+        let (nodes, a, slow, _) = build("Slow");
+        let (result, outputs) =
+            engine.process_cancellable(&(), &nodes, a, &CancellationToken::new());
+        assert!(matches!(
+            result.unwrap_err(),
+            EngineError::Timeout { node_id } if node_id == slow
+        ));
+        assert_eq!(outputs.len(), 1);
+    }
+
+    #[test]
+    fn limits_are_enforced() {
+        struct Blob;
+        impl Worker<()> for Blob {
+            fn name(&self) -> &str {
+                "Blob"
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                _node: &Node,
+                _input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                Ok(HashMap::from([(
+                    "num".to_string(),
+                    OutputValue::Bytes(vec![0; 16]),
+                )]))
+            }
+        }
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(1))]));
+        let mut adds = vec![];
+        let mut last = a;
+        for _ in 0..4 {
+            let add = builder.add_node("Add", HashMap::new());
+            builder
+                .connect(last, "num", add, "num")
+                .unwrap()
+                .connect(a, "num", add, "num2")
+                .unwrap();
+            adds.push(add);
+            last = add;
+        }
+        let graph = builder.build();
+        let engine = |limits: Limits| {
+            let mut workers = WorkersBuilder::default();
+
+            workers.add(Number);
+            workers.add(Add);
+            workers.add(Blob);
+
+            let options = EngineOptions {
+                limits,
+                ..Default::default()
+            };
+            Engine::with_options("demo@0.1.0".to_string(), workers.build(), options)
+        };
+
+        let limited = engine(Limits::default());
+        assert_eq!(
+            limited.process(&(), &graph.nodes, a).unwrap()["num"],
+            OutputValue::I64(5)
+        );
+
+        let limited = engine(Limits {
+            max_node_executions: Some(3),
+            ..Default::default()
+        });
+        assert!(matches!(
+            limited.process(&(), &graph.nodes, a).unwrap_err(),
+            EngineError::ExecutionLimitExceeded { limit: 3 }
+        ));
+
+        let limited = engine(Limits {
+            max_depth: Some(5),
+            ..Default::default()
+        });
+        assert!(limited.process(&(), &graph.nodes, a).is_ok());
+
+        let limited = engine(Limits {
+            max_depth: Some(3),
+            ..Default::default()
+        });
+        assert!(matches!(
+            limited.process(&(), &graph.nodes, a).unwrap_err(),
+            EngineError::DepthLimitExceeded { node_id, limit: 3 } if node_id == adds[2]
+        ));
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let first = builder.add_node("Add", HashMap::new());
+        let second = builder.add_node("Add", HashMap::new());
+        let third = builder.add_node("Add", HashMap::new());
+        builder
+            .connect(first, "num", second, "num")
+            .unwrap()
+            .connect(second, "num", third, "num")
+            .unwrap()
+            .connect(third, "num", first, "num")
+            .unwrap();
+        let cycle = builder.build_nodes();
+        let limited = engine(Limits {
+            max_node_executions: Some(100),
+            ..Default::default()
+        });
+        assert!(matches!(
+            limited.process(&(), &cycle, first).unwrap_err(),
+            EngineError::CycleDetected { node_id } if node_id == first
+        ));
+
+        // every path through a lattice of diamonds reaches the last node, the
+        // links of each node are still only followed once
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let start = builder.add_node("Number", HashMap::from([("num".to_string(), json!(1))]));
+        let mut layer = vec![start, start];
+        for _ in 0..20 {
+            let next: Vec<i64> = (0..2)
+                .map(|_| builder.add_node("Add", HashMap::new()))
+                .collect();
+            for &node in &next {
+                builder
+                    .connect(layer[0], "num", node, "num")
+                    .unwrap()
+                    .connect(layer[1], "num", node, "num2")
+                    .unwrap();
+            }
+            layer = next;
+        }
+        let lattice = builder.build_nodes();
+        let limited = engine(Limits {
+            max_traversal_steps: Some(1000),
+            ..Default::default()
+        });
+        assert_eq!(
+            limited.process(&(), &lattice, start).unwrap()["num"],
+            OutputValue::I64(1 << 20)
+        );
+        let limited = engine(Limits {
+            max_traversal_steps: Some(50),
+            ..Default::default()
+        });
+        assert!(matches!(
+            limited.process(&(), &lattice, start).unwrap_err(),
+            EngineError::TraversalLimitExceeded { limit: 50 }
+        ));
+
+        let limited = engine(Limits {
+            max_graph_nodes: Some(4),
+            ..Default::default()
+        });
+        let value = serde_json::to_value(&graph).unwrap();
+        assert!(matches!(
+            limited.parse_value(value).unwrap_err(),
+            EngineError::GraphTooLarge { nodes: 5, limit: 4 }
+        ));
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let first = builder.add_node("Blob", HashMap::new());
+        let second = builder.add_node("Blob", HashMap::new());
+        builder.connect(first, "num", second, "num").unwrap();
+        let nodes = builder.build_nodes();
+        let limited = engine(Limits {
+            max_cache_bytes: Some(20),
+            ..Default::default()
+        });
+        assert!(matches!(
+            limited.process(&(), &nodes, first).unwrap_err(),
+            EngineError::CacheLimitExceeded { node_id, limit: 20 } if node_id == second
+        ));
+    }
+
+    #[test]
+    fn continue_on_error_works() {
+        struct Fail;
+        impl Worker<()> for Fail {
+            fn name(&self) -> &str {
+                "Fail"
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                _node: &Node,
+                _input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                bail!("boom")
+            }
+        }
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let fail = builder.add_node("Fail", HashMap::new());
+        let failed_add = builder.add_node("Add", HashMap::new());
+        let add = builder.add_node("Add", HashMap::new());
+        builder
+            .connect(a, "num", fail, "num")
+            .unwrap()
+            .connect(fail, "num", failed_add, "num")
+            .unwrap()
+            .connect(a, "num", failed_add, "num2")
+            .unwrap()
+            .connect(a, "num", add, "num")
+            .unwrap()
+            .connect(a, "num", add, "num2")
+            .unwrap();
+        let nodes = builder.build_nodes();
+        let engine = |error_policy: ErrorPolicy| {
+            let mut workers = WorkersBuilder::default();
+
+            workers.add(Number);
+            workers.add(Add);
+            workers.add(Fail);
+
+            let options = EngineOptions {
+                error_policy,
+                ..Default::default()
+            };
+            Engine::with_options("demo@0.1.0".to_string(), workers.build(), options)
+        };
+
+        let fail_fast = engine(ErrorPolicy::FailFast);
+        assert!(fail_fast.process_report(&(), &nodes, a).is_err());
+
+        let continuing = engine(ErrorPolicy::Continue);
+        let report = continuing.process_report(&(), &nodes, a).unwrap();
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].node_id, fail);
+        assert_eq!(report.nodes[&add]["num"], OutputValue::I64(4));
+        assert!(report.nodes.contains_key(&a));
+        assert!(!report.nodes.contains_key(&failed_add));
+        assert!(continuing.process(&(), &nodes, a).is_err());
+
+        let (_, trace) = continuing.process_traced(&(), &nodes, a);
+        assert_eq!(trace.nodes[&fail].status, NodeStatus::Failed);
+        assert_eq!(trace.nodes[&add].status, NodeStatus::Ran);
+        assert_eq!(
+            trace.nodes[&failed_add].skip_reason,
+            Some(SkipReason::UpstreamFailed { node_id: fail })
+        );
+    }
+
+    #[test]
+    fn error_outputs_work() {
+        struct Risky;
+        impl Worker<()> for Risky {
+            fn name(&self) -> &str {
+                "Risky"
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                node: &Node,
+                input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                if node.get_data::<bool>("fail")?.unwrap_or(false) {
+                    return Err(anyhow!("bad input").context("risky failed"));
+                }
+                Ok(HashMap::from([
+                    ("num".to_string(), input_data["num"].clone()),
+                    ("action".to_string(), OutputValue::I64(0)),
+                ]))
+            }
+        }
+
+        struct Catch;
+        impl Worker<()> for Catch {
+            fn name(&self) -> &str {
+                "Catch"
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                _node: &Node,
+                input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                Ok(input_data)
+            }
+        }
+
+        let build = |fail: bool, gated: bool| {
+            let mut builder = GraphBuilder::new("demo@0.1.0");
+            let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+            let risky =
+                builder.add_node("Risky", HashMap::from([("fail".to_string(), json!(fail))]));
+            let add = builder.add_node("Add", HashMap::new());
+            let catch = builder.add_node("Catch", HashMap::new());
+            builder
+                .connect(a, "num", risky, "num")
+                .unwrap()
+                .connect(risky, "num", add, "num")
+                .unwrap()
+                .connect(a, "num", add, "num2")
+                .unwrap()
+                .connect(risky, ERROR_OUTPUT_KEY, catch, "message")
+                .unwrap();
+            if gated {
+                builder.connect(risky, "action", add, "action").unwrap();
+            }
+            (builder.build_nodes(), a, add, catch)
+        };
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Add);
+        workers.add(Risky);
+        workers.add(Catch);
+
+        let engine = Engine::new("demo@0.1.0".to_string(), workers.build());
+
+        let (nodes, a, add, catch) = build(false, true);
+        let report = engine.process_report(&(), &nodes, a).unwrap();
+        assert_eq!(report.nodes[&add]["num"], OutputValue::I64(4));
+        assert!(!report.nodes.contains_key(&catch));
+
+        // the node behind the failed one is skipped whether or not an action gates it
+        for gated in [true, false] {
+            let (nodes, a, add, catch) = build(true, gated);
+            let report = engine.process_report(&(), &nodes, a).unwrap();
+            assert!(report.errors.is_empty());
+            assert!(!report.nodes.contains_key(&add));
+            assert_eq!(
+                report.nodes[&catch]["message"],
+                OutputValue::String("risky failed: bad input".to_string())
+            );
+            assert!(engine.process(&(), &nodes, a).is_ok());
+        }
+    }
+
+    #[test]
+    fn retries_work() {
+        struct Flaky(Rc<Cell<u32>>);
+        impl Worker<()> for Flaky {
+            fn name(&self) -> &str {
+                "Flaky"
+            }
+
+            fn invoke(
+                &self,
+                invocation: &Invocation<'_, ()>,
+                node: &Node,
+                input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                if node.get_data::<bool>("cancel")?.unwrap_or(false) {
+                    invocation.cancellation().unwrap().cancel();
+                }
+                self.work(invocation.context(), node, input_data)
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                _node: &Node,
+                input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                if self.0.get() > 0 {
+                    self.0.set(self.0.get() - 1);
+                    bail!("unavailable");
+                }
+                Ok(input_data)
+            }
+        }
+
+        #[derive(Clone, Default)]
+        struct FakeClock(Rc<RefCell<Vec<Duration>>>);
+        impl Clock for FakeClock {
+            fn sleep(&self, duration: Duration) {
+                self.0.borrow_mut().push(duration);
+            }
+        }
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let flaky = builder.add_node("Flaky", HashMap::new());
+        builder.connect(a, "num", flaky, "num").unwrap();
+        let nodes = builder.build_nodes();
+
+        let failures = Rc::new(Cell::new(0));
+        let clock = FakeClock::default();
+        let engine_with = |options: EngineOptions| {
+            let mut workers = WorkersBuilder::default();
+
+            workers.add(Number);
+            workers.add(Flaky(failures.clone()));
+
+            let mut engine =
+                Engine::with_options("demo@0.1.0".to_string(), workers.build(), options);
+            engine.set_clock(clock.clone());
+            engine
+        };
+        let engine = |retries: Retries| {
+            engine_with(EngineOptions {
+                retries,
+                ..Default::default()
+            })
+        };
+        let ms = Duration::from_millis;
+        let policy = RetryPolicy::new(3).backoff(Backoff::Exponential {
+            initial: ms(10),
+            max: ms(15),
+        });
+
+        let retrying = engine(Retries::default().worker("Flaky", policy.clone()));
+        failures.set(2);
+        let (result, trace) = retrying.process_traced(&(), &nodes, a);
+        assert_eq!(result.unwrap()["num"], OutputValue::I64(2));
+        assert_eq!(*clock.0.borrow(), vec![ms(10), ms(15)]);
+        let retries = &trace.nodes[&flaky].retries;
+        assert_eq!(retries.len(), 2);
+        assert_eq!(retries[1].attempt, 2);
+        assert_eq!(retries[1].delay_micros, 15_000);
+
+        failures.set(3);
+        assert!(retrying.process(&(), &nodes, a).is_err());
+        assert_eq!(failures.get(), 0);
+
+        let retrying = engine(Retries::default().worker("Flaky", policy).node(
+            flaky,
+            RetryPolicy::new(3).retry_if(|e| e.to_string() != "unavailable"),
+        ));
+        failures.set(1);
+        clock.0.borrow_mut().clear();
+        assert!(retrying.process(&(), &nodes, a).is_err());
+        assert!(clock.0.borrow().is_empty());
+
+        // no waiting past the end of the run
+        let retrying = engine_with(EngineOptions {
+            retries: Retries::default().worker(
+                "Flaky",
+                RetryPolicy::new(3).backoff(Backoff::Fixed(ms(60_000))),
+            ),
+            run_timeout: Some(ms(1_000)),
+            ..Default::default()
+        });
+        failures.set(2);
+        assert!(matches!(
+            retrying.process(&(), &nodes, a).unwrap_err(),
+            EngineError::Timeout { node_id } if node_id == flaky
+        ));
+        assert!(clock.0.borrow().is_empty());
+
+        let retrying = engine_with(EngineOptions {
+            retries: Retries::default().worker("Flaky", RetryPolicy::new(3)),
+            ..Default::default()
+        });
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let flaky = builder.add_node(
+            "Flaky",
+            HashMap::from([("cancel".to_string(), json!(true))]),
+        );
+        builder.connect(a, "num", flaky, "num").unwrap();
+        let nodes = builder.build_nodes();
+        failures.set(2);
+        let (result, _) = retrying.process_cancellable(&(), &nodes, a, &CancellationToken::new());
+        assert!(matches!(result.unwrap_err(), EngineError::Cancelled));
+        assert_eq!(failures.get(), 1);
+        assert!(clock.0.borrow().is_empty());
+    }
+
+    #[test]
+    fn typed_errors_work() {
+        struct Fail;
+        impl Worker<()> for Fail {
+            fn name(&self) -> &str {
+                "Fail"
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                _node: &Node,
+                _input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                Err(anyhow!("boom").context("fetching"))
+            }
+        }
+
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Fail);
+
+        let engine = Engine::new("demo@0.1.0".to_string(), workers.build());
+        let error = engine.parse_json("{").unwrap_err();
+        assert!(matches!(error, EngineError::Parse { .. }));
+        assert_eq!(error.json_path(), Some("."));
+        let error = engine
+            .parse_json(r#"{ "id": "demo@0.1.0", "nodes": { "1": { "id": "x" } } }"#)
+            .unwrap_err();
+        assert_eq!(error.json_path(), Some("nodes.1.id"));
+        assert!(matches!(
+            engine.parse_json(r#"{ "nodes": {} }"#).unwrap_err(),
+            EngineError::MissingGraphId
+        ));
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let fail = builder.add_node("Fail", HashMap::new());
+        builder.connect(a, "num", fail, "num").unwrap();
+        let nodes = builder.build_nodes();
+        let error = engine.process(&(), &nodes, a).unwrap_err();
+        assert_eq!(error.node_id(), Some(fail));
+        match &error {
+            EngineError::WorkerError(e) => assert_eq!(e.worker(), Some("Fail")),
+            _ => panic!("expected a worker error, got {}", error),
+        }
+        let chain: Vec<String> = error.chain().map(|e| e.to_string()).collect();
+        assert_eq!(chain.last().unwrap(), "boom");
+        assert!(chain.contains(&"fetching".to_string()));
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let missing = builder.add_node("Missing", HashMap::new());
+        builder.connect(a, "num", missing, "num").unwrap();
+        let error = engine.process(&(), &builder.build_nodes(), a).unwrap_err();
+        assert_eq!(error.node_id(), Some(missing));
+        assert!(matches!(
+            error,
+            EngineError::WorkerError(WorkerError::WorkerNotFound { worker, .. }) if worker == "Missing"
+        ));
+    }
+
+    #[test]
+    fn panics_are_isolated() {
+        struct Crash;
+        impl Worker<()> for Crash {
+            fn name(&self) -> &str {
+                "Crash"
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                _node: &Node,
+                _input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                panic!("worker crashed")
+            }
+        }
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let crash = builder.add_node("Crash", HashMap::new());
+        let add = builder.add_node("Add", HashMap::new());
+        builder
+            .connect(a, "num", crash, "num")
+            .unwrap()
+            .connect(a, "num", add, "num")
+            .unwrap()
+            .connect(a, "num", add, "num2")
+            .unwrap();
+        let nodes = builder.build_nodes();
+
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Add);
+        workers.add(Crash);
+
+        let options = EngineOptions {
+            catch_panics: true,
+            error_policy: ErrorPolicy::Continue,
+            ..Default::default()
+        };
+        let engine = Engine::with_options("demo@0.1.0".to_string(), workers.build(), options);
+        let report = engine.process_report(&(), &nodes, a).unwrap();
+        assert_eq!(report.nodes[&add]["num"], OutputValue::I64(4));
+        assert_eq!(report.errors.len(), 1);
+        assert!(matches!(
+            &report.errors[0].error,
+            EngineError::WorkerError(WorkerError::Panicked { node_id, worker, message })
+                if *node_id == crash && worker == "Crash" && message == "worker crashed"
+        ));
+
+        // the engine is still usable after a panic
+        let report = engine.process_report(&(), &nodes, a).unwrap();
+        assert_eq!(report.errors.len(), 1);
+
+        struct Catch;
+        impl Worker<()> for Catch {
+            fn name(&self) -> &str {
+                "Catch"
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                _node: &Node,
+                input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                Ok(input_data)
+            }
+        }
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let crash = builder.add_node("Crash", HashMap::new());
+        let catch = builder.add_node("Catch", HashMap::new());
+        builder
+            .connect(a, "num", crash, "num")
+            .unwrap()
+            .connect(crash, ERROR_OUTPUT_KEY, catch, "message")
+            .unwrap();
+        let nodes = builder.build_nodes();
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Crash);
+        workers.add(Catch);
+
+        let options = EngineOptions {
+            catch_panics: true,
+            ..Default::default()
+        };
+        let engine = Engine::with_options("demo@0.1.0".to_string(), workers.build(), options);
+        let report = engine.process_report(&(), &nodes, a).unwrap();
+        assert_eq!(
+            report.nodes[&catch]["message"],
+            OutputValue::String("worker crashed".to_string())
+        );
+    }
+
+    #[test]
+    fn layers_wrap_workers() {
+        #[derive(Clone, Default)]
+        struct Audit(Rc<RefCell<Vec<String>>>);
+        impl Layer<()> for Audit {
+            fn call(
+                &self,
+                context: &(),
+                node: &Node,
+                input_data: HashMap<String, OutputValue>,
+                next: Next<'_, ()>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                self.0.borrow_mut().push(next.worker().name().to_string());
+                if node.name == "Multiply" {
+                    return Ok(HashMap::from([("num".to_string(), OutputValue::I64(100))]));
+                }
+                next.run(context, node, input_data)
+            }
+        }
+
+        struct Word;
+        impl Worker<()> for Word {
+            fn name(&self) -> &str {
+                "Word"
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                _node: &Node,
+                _input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                Ok(HashMap::from([(
+                    "num".to_string(),
+                    OutputValue::String("two".to_string()),
+                )]))
+            }
+        }
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let b = builder.add_node("Number", HashMap::from([("num".to_string(), json!(3))]));
+        let add = builder.add_node("Add", HashMap::new());
+        let multiply = builder.add_node("Multiply", HashMap::new());
+        builder
+            .connect(a, "num", add, "num")
+            .unwrap()
+            .connect(b, "num", add, "num2")
+            .unwrap()
+            .connect(add, "num", multiply, "num")
+            .unwrap()
+            .connect(b, "num", multiply, "num2")
+            .unwrap();
+        let nodes = builder.build_nodes();
+
+        let audit = Audit::default();
+        let timing = TimingLayer::default();
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Number);
+        workers.add(Add);
+        workers.add(Multiply);
+        workers.layer(audit.clone()).layer(timing.clone());
+
+        let engine = Engine::new("demo@0.1.0".to_string(), workers.build());
+        let output = engine.process(&(), &nodes, a).unwrap();
+        assert_eq!(output["num"], OutputValue::I64(100));
+        assert_eq!(
+            *audit.0.borrow(),
+            vec!["Number", "Number", "Add", "Multiply"]
+        );
+        let timings = timing.timings();
+        assert_eq!(timings["Number"].calls, 2);
+        assert_eq!(timings["Add"].calls, 1);
+        assert!(!timings.contains_key("Multiply"));
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let word = builder.add_node("Word", HashMap::new());
+        let add = builder.add_node("Add", HashMap::new());
+        builder
+            .connect(word, "num", add, "num")
+            .unwrap()
+            .connect(word, "num", add, "num2")
+            .unwrap();
+        let nodes = builder.build_nodes();
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Word);
+        workers.add(Add);
+        workers.layer(ValidationLayer);
+
+        let engine = Engine::new("demo@0.1.0".to_string(), workers.build());
+        let error = engine.process(&(), &nodes, word).unwrap_err();
+        assert_eq!(error.node_id(), Some(add));
+        let schema_error = error
+            .chain()
+            .find_map(|e| e.downcast_ref::<SchemaError>())
+            .unwrap();
+        assert!(matches!(
+            schema_error,
+            SchemaError::InputTypeMismatch {
+                expected: SocketType::I64,
+                ..
+            }
+        ));
+
+        // a declared output of the wrong type fails as it does under
+        // `EngineOptions::check_output_types`
+        struct Liar;
+        impl Worker<()> for Liar {
+            fn name(&self) -> &str {
+                "Liar"
+            }
+
+            fn schema(&self) -> ComponentSchema {
+                ComponentSchema::new("Liar").output(PortSchema::new("num", SocketType::I64))
+            }
+
+            fn work(
+                &self,
+                context: &(),
+                node: &Node,
+                input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                Word.work(context, node, input_data)
+            }
+        }
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let liar = builder.add_node("Liar", HashMap::new());
+        let nodes = builder.build_nodes();
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Liar);
+        workers.layer(ValidationLayer);
+
+        let engine = Engine::new("demo@0.1.0".to_string(), workers.build());
+        let error = engine.process(&(), &nodes, liar).unwrap_err();
+        assert!(error.chain().any(|e| matches!(
+            e.downcast_ref::<EngineError>(),
+            Some(EngineError::OutputTypeMismatch { node_id, expected: SocketType::I64, .. })
+                if *node_id == liar
+        )));
+    }
+
+    #[test]
+    fn run_state_works() {
+        struct Counted;
+        impl Worker<()> for Counted {
+            fn name(&self) -> &str {
+                "Counted"
+            }
+
+            fn invoke(
+                &self,
+                invocation: &Invocation<'_, ()>,
+                node: &Node,
+                input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                let output = self.work(invocation.context(), node, input_data)?;
+                let calls = invocation.state().update(|calls: &mut u32| {
+                    *calls += 1;
+                    *calls
+                });
+                invocation.emit(
+                    "counted",
+                    json!({ "num": output["num"].as_i64()?, "calls": calls }),
+                );
+                Ok(output)
+            }
+
+            fn work(
+                &self,
+                _context: &(),
+                node: &Node,
+                _input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                let num: i64 = node.get_data("num")?.unwrap();
+                Ok(HashMap::from([("num".to_string(), OutputValue::I64(num))]))
+            }
+        }
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Counted", HashMap::from([("num".to_string(), json!(2))]));
+        let b = builder.add_node("Counted", HashMap::from([("num".to_string(), json!(3))]));
+        let add = builder.add_node("Add", HashMap::new());
+        builder
+            .connect(a, "num", add, "num")
+            .unwrap()
+            .connect(b, "num", add, "num2")
+            .unwrap();
+        let nodes = builder.build_nodes();
+        let mut workers = WorkersBuilder::default();
+
+        workers.add(Counted);
+        workers.add(Add);
+
+        let engine = Engine::new("demo@0.1.0".to_string(), workers.build());
+        let report = engine.process_report(&(), &nodes, a).unwrap();
+        assert_eq!(report.outputs["num"], OutputValue::I64(5));
+        assert_eq!(report.state.get::<u32>(), Some(2));
+        let events = report.state.events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].node_id, a);
+        assert_eq!(events[0].kind, "counted");
+        assert_eq!(events[1].data, json!({ "num": 3, "calls": 2 }));
+
+        // every run starts with fresh state
+        let report = engine.process_report(&(), &nodes, a).unwrap();
+        assert_eq!(report.state.get::<u32>(), Some(2));
+    }
+
+    #[test]
+    fn node_state_works() {
+        struct Accumulate;
+        impl Worker<()> for Accumulate {
+            fn name(&self) -> &str {
+                "Accumulate"
+            }
+
+            fn invoke(
+                &self,
+                invocation: &Invocation<'_, ()>,
+                node: &Node,
+                input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                let num = self.work(invocation.context(), node, input_data)?["num"].as_i64()?;
+                let total = invocation.update_node_state(|total: &mut i64| {
+                    *total += num;
+                    *total
+                })?;
+                Ok(HashMap::from([(
+                    "num".to_string(),
+                    OutputValue::I64(total),
+                )]))
+            }
+
+            /// Without state every run starts from zero
+            fn work(
+                &self,
+                _context: &(),
+                _node: &Node,
+                input_data: HashMap<String, OutputValue>,
+            ) -> Result<HashMap<String, OutputValue>> {
+                let num = input_data
+                    .get("num")
+                    .ok_or_else(|| anyhow!("Missing input: num"))?;
+                Ok(HashMap::from([("num".to_string(), num.clone())]))
+            }
+        }
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let a = builder.add_node("Number", HashMap::from([("num".to_string(), json!(2))]));
+        let first = builder.add_node("Accumulate", HashMap::new());
+        let second = builder.add_node("Accumulate", HashMap::new());
+        builder
+            .connect(a, "num", first, "num")
+            .unwrap()
+            .connect(first, "num", second, "num")
+            .unwrap();
+        let nodes = builder.build_nodes();
+        let workers = || {
+            let mut workers = WorkersBuilder::default();
+            workers.add(Number);
+            workers.add(Accumulate);
+            workers.build()
+        };
+
+        let mut builder = GraphBuilder::new("demo@0.1.0");
+        let b = builder.add_node("Number", HashMap::from([("num".to_string(), json!(5))]));
+        let other_first = builder.add_node("Accumulate", HashMap::new());
+        builder.connect(b, "num", other_first, "num").unwrap();
+        let other = builder.build_nodes();
+        assert_eq!(other_first, first);
+
+        let engine = Engine::new("demo@0.1.0".to_string(), workers());
+        let error = engine
+            .run_with(&(), &nodes, a, RunOptions::new().graph_key("doc-1"))
+            .into_result()
+            .unwrap_err();
+        assert_eq!(error.node_id(), Some(first));
+
+        let mut engine = Engine::new("demo@0.1.0".to_string(), workers());
+        engine.set_state_store(MemoryStateStore::default());
+        // without a graph key there is nowhere to keep the state
+        assert!(engine.process(&(), &nodes, a).is_err());
+        let output = engine
+            .run_with(&(), &nodes, a, RunOptions::new().graph_key("doc-1"))
+            .into_result()
+            .unwrap();
+        assert_eq!(output["num"], OutputValue::I64(2));
+        let output = engine
+            .run_with(&(), &nodes, a, RunOptions::new().graph_key("doc-1"))
+            .into_result()
+            .unwrap();
+        assert_eq!(output["num"], OutputValue::I64(6));
+        // same node ids, different graph
+        let output = engine
+            .run_with(&(), &other, b, RunOptions::new().graph_key("doc-2"))
+            .into_result()
+            .unwrap();
+        assert_eq!(output["num"], OutputValue::I64(5));
+        let output = engine
+            .run_with(&(), &other, b, RunOptions::new().graph_key("doc-2"))
+            .into_result()
+            .unwrap();
+        assert_eq!(output["num"], OutputValue::I64(10));
+        let store = engine.state_store().unwrap();
+        assert_eq!(
+            store.get(&StateKey::new("doc-1", first)).unwrap(),
+            Some(json!(4))
+        );
+
+        // the state outlives the engine and doesn't depend on its version
+        let path =
+            std::env::temp_dir().join(format!("d3ne-node-state-{}.json", std::process::id()));
+        let mut engine = Engine::new("demo@0.1.0".to_string(), workers());
+        engine.set_state_store(FileStateStore::new(&path).unwrap());
+        engine
+            .run_with(&(), &nodes, a, RunOptions::new().graph_key("doc-1"))
+            .into_result()
+            .unwrap();
+        let mut engine = Engine::new("demo@0.2.0".to_string(), workers());
+        engine.set_state_store(FileStateStore::new(&path).unwrap());
+        let output = engine
+            .run_with(&(), &nodes, a, RunOptions::new().graph_key("doc-1"))
+            .into_result()
+            .unwrap();
+        assert_eq!(output["num"], OutputValue::I64(6));
+        std::fs::remove_file(path).unwrap();
+
+        // state, cancellation, tracing and sessions all combine through `RunOptions`
+        let mut engine = Engine::new("demo@0.1.0".to_string(), workers());
+        engine.set_state_store(MemoryStateStore::default());
+        let token = CancellationToken::new();
+        let recorder = TraceRecorder::default();
+        let outcome = engine.run_with(
+            &(),
+            &nodes,
+            a,
+            RunOptions::new()
+                .graph_key("doc-1")
+                .token(&token)
+                .observer(&recorder),
+        );
+        assert_eq!(outcome.result.unwrap()["num"], OutputValue::I64(2));
+        assert_eq!(outcome.nodes.len(), nodes.len());
+        assert_eq!(
+            recorder.finish(&nodes).nodes[&first].status,
+            NodeStatus::Ran
+        );
+        let mut session = engine
+            .session(nodes.clone(), a)
+            .with_options(RunOptions::new().graph_key("doc-1").token(&token));
+        assert_eq!(session.run(&()).unwrap()["num"], OutputValue::I64(6));
+        token.cancel();
+        session.invalidate(first);
+        assert!(matches!(
+            session.run(&()).unwrap_err(),
+            EngineError::Cancelled
+        ));
+        let mut debugger = engine.debug_with(&(), &nodes, a, RunOptions::new().graph_key("doc-1"));
+        assert!(debugger.resume().is_ok());
+        assert_eq!(
+            engine
+                .state_store()
+                .unwrap()
+                .get(&StateKey::new("doc-1", second))
+                .unwrap(),
+            Some(json!(12))
+        );
+    }
+
     struct Number;
     impl Worker<()> for Number {
         fn name(&self) -> &str {
             "Number"
         }
 
+        fn schema(&self) -> ComponentSchema {
+            ComponentSchema::new("Number")
+                .output(PortSchema::new("num", SocketType::I64))
+                .control(ControlSchema::new("num", ControlType::Integer))
+        }
+
         fn work(
             &self,
             _context: &(),
@@ -533,6 +2427,14 @@ mod tests {
             "Add"
         }
 
+        fn schema(&self) -> ComponentSchema {
+            ComponentSchema::new("Add")
+                .category("Math")
+                .input(PortSchema::new("num", SocketType::I64))
+                .input(PortSchema::new("num2", SocketType::I64))
+                .output(PortSchema::new("num", SocketType::I64))
+        }
+
         fn work(
             &self,
             _context: &(),
@@ -561,6 +2463,14 @@ mod tests {
             "Multiply"
         }
 
+        fn schema(&self) -> ComponentSchema {
+            ComponentSchema::new("Multiply")
+                .category("Math")
+                .input(PortSchema::new("num", SocketType::I64))
+                .input(PortSchema::new("num2", SocketType::I64))
+                .output(PortSchema::new("num", SocketType::I64))
+        }
+
         fn work(
             &self,
             _context: &(),
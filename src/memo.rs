@@ -0,0 +1,362 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::node::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::PathBuf;
+
+/// Everything a pure worker's result depends on: the worker name and version,
+/// the node data and the input values. It is kept as canonical JSON, with object
+/// keys sorted, so equal keys are equal across builds and on-disk stores can
+/// check what they read. The FNV-1a hash only picks a slot, it is never trusted
+/// on its own.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MemoKey {
+    hash: u64,
+    material: String,
+}
+
+impl MemoKey {
+    pub fn new(
+        name: &str,
+        version: u32,
+        node: &Node,
+        input: &HashMap<String, OutputValue>,
+    ) -> Self {
+        let data: Map<String, Value> = node
+            .data
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let input: Map<String, Value> = input
+            .iter()
+            .map(|(k, v)| (k.clone(), serde_json::to_value(v).unwrap_or_default()))
+            .collect();
+        let mut material = String::new();
+        write_canonical(
+            &json!({ "name": name, "version": version, "data": data, "input": input }),
+            &mut material,
+        );
+        let mut hasher = Fnv::default();
+        hasher.write_str(&material);
+        Self {
+            hash: hasher.0,
+            material,
+        }
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// The canonical JSON the key was built from
+    pub fn material(&self) -> &str {
+        &self.material
+    }
+}
+
+impl Display for MemoKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.hash)
+    }
+}
+
+/// Writes `value` as JSON with the keys of every object sorted, whatever order
+/// the map keeps them in
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(&map[key], out);
+            }
+            out.push('}');
+        }
+        Value::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(value, out);
+            }
+            out.push(']');
+        }
+        value => out.push_str(&value.to_string()),
+    }
+}
+
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+}
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.write(&(s.len() as u64).to_le_bytes());
+        self.write(s.as_bytes());
+    }
+}
+
+/// Where memoized worker outputs are kept
+pub trait MemoStore {
+    fn get(&self, key: &MemoKey) -> Option<HashMap<String, OutputValue>>;
+    fn put(&self, key: MemoKey, output: &HashMap<String, OutputValue>);
+}
+
+/// Keeps the `capacity` most recently used results in memory
+pub struct LruMemoStore {
+    capacity: usize,
+    entries: RefCell<HashMap<MemoKey, HashMap<String, OutputValue>>>,
+    order: RefCell<VecDeque<MemoKey>>,
+}
+
+impl LruMemoStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: RefCell::new(HashMap::new()),
+            order: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    fn touch(&self, key: &MemoKey) {
+        let mut order = self.order.borrow_mut();
+        order.retain(|k| k != key);
+        order.push_back(key.clone());
+    }
+}
+
+impl MemoStore for LruMemoStore {
+    fn get(&self, key: &MemoKey) -> Option<HashMap<String, OutputValue>> {
+        let output = self.entries.borrow().get(key).cloned();
+        if output.is_some() {
+            self.touch(key);
+        }
+        output
+    }
+
+    fn put(&self, key: MemoKey, output: &HashMap<String, OutputValue>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.touch(&key);
+        self.entries.borrow_mut().insert(key, output.clone());
+        while self.order.borrow().len() > self.capacity {
+            if let Some(oldest) = self.order.borrow_mut().pop_front() {
+                self.entries.borrow_mut().remove(&oldest);
+            }
+        }
+    }
+}
+
+/// One JSON file per result in `dir`, named by the key hash. Files that can't be
+/// read, or that hold another key with the same hash, are treated as misses.
+pub struct FileMemoStore {
+    dir: PathBuf,
+}
+
+impl FileMemoStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> std::io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: &MemoKey) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct MemoFile {
+    key: String,
+    output: HashMap<String, OutputValue>,
+}
+
+impl MemoStore for FileMemoStore {
+    fn get(&self, key: &MemoKey) -> Option<HashMap<String, OutputValue>> {
+        let json = fs::read_to_string(self.path(key)).ok()?;
+        let file: MemoFile = serde_json::from_str(&json).ok()?;
+        (file.key == key.material).then_some(file.output)
+    }
+
+    fn put(&self, key: MemoKey, output: &HashMap<String, OutputValue>) {
+        let file = MemoFile {
+            key: key.material.clone(),
+            output: output.clone(),
+        };
+        if let Ok(json) = serde_json::to_string(&file) {
+            // a failed write only costs a recomputation later
+            let _ = fs::write(self.path(&key), json);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// A store plus hit and miss counters, used by the engine for pure workers
+pub struct Memo {
+    store: Box<dyn MemoStore>,
+    hits: Cell<u64>,
+    misses: Cell<u64>,
+}
+
+impl Memo {
+    pub fn new<S: MemoStore + 'static>(store: S) -> Self {
+        Self {
+            store: Box::new(store),
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &MemoKey) -> Option<HashMap<String, OutputValue>> {
+        let output = self.store.get(key);
+        match output {
+            Some(_) => self.hits.set(self.hits.get() + 1),
+            None => self.misses.set(self.misses.get() + 1),
+        }
+        output
+    }
+
+    pub fn put(&self, key: MemoKey, output: &HashMap<String, OutputValue>) {
+        self.store.put(key, output)
+    }
+
+    pub fn stats(&self) -> MemoStats {
+        MemoStats {
+            hits: self.hits.get(),
+            misses: self.misses.get(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn node(data: serde_json::Value) -> Node {
+        Node {
+            id: 1,
+            name: "Hash".to_string(),
+            data: serde_json::from_value(data).unwrap(),
+            group: None,
+            position: None,
+            inputs: Default::default(),
+            outputs: Default::default(),
+            extra: Default::default(),
+        }
+    }
+
+    fn key(num: i64) -> MemoKey {
+        let input = HashMap::from([("num".to_string(), OutputValue::I64(num))]);
+        MemoKey::new("Hash", 1, &node(json!({})), &input)
+    }
+
+    #[test]
+    fn test_key() {
+        let input = HashMap::from([("num".to_string(), OutputValue::I64(1))]);
+        let data = json!({ "a": 1, "b": { "y": 2, "x": [{ "q": 1, "p": 2 }] } });
+        let key = MemoKey::new("Hash", 1, &node(data.clone()), &input);
+        assert_eq!(
+            key,
+            MemoKey::new(
+                "Hash",
+                1,
+                &node(json!({ "b": { "x": [{ "p": 2, "q": 1 }], "y": 2 }, "a": 1 })),
+                &input
+            )
+        );
+        assert_eq!(
+            key.material(),
+            r#"{"data":{"a":1,"b":{"x":[{"p":2,"q":1}],"y":2}},"input":{"num":{"I64":1}},"name":"Hash","version":1}"#
+        );
+        assert_ne!(
+            key,
+            MemoKey::new("Hash", 1, &node(json!({ "a": 1, "b": 3 })), &input)
+        );
+        assert_ne!(key, MemoKey::new("Other", 1, &node(data.clone()), &input));
+        assert_ne!(key, MemoKey::new("Hash", 2, &node(data.clone()), &input));
+        let input = HashMap::from([("num".to_string(), OutputValue::U64(1))]);
+        assert_ne!(key, MemoKey::new("Hash", 1, &node(data), &input));
+    }
+
+    #[test]
+    fn test_lru() {
+        let store = LruMemoStore::new(2);
+        let output = HashMap::from([("num".to_string(), OutputValue::I64(1))]);
+        store.put(key(1), &output);
+        store.put(key(2), &output);
+        assert!(store.get(&key(1)).is_some());
+        store.put(key(3), &output);
+        assert_eq!(store.len(), 2);
+        assert!(store.get(&key(2)).is_none());
+        assert!(store.get(&key(1)).is_some());
+    }
+
+    #[test]
+    fn test_file_store() {
+        let dir = std::env::temp_dir().join(format!("d3ne-memo-{}", std::process::id()));
+        let store = FileMemoStore::new(&dir).unwrap();
+        let output = HashMap::from([("bytes".to_string(), OutputValue::Bytes(vec![1, 2]))]);
+        assert!(store.get(&key(7)).is_none());
+        store.put(key(7), &output);
+        assert_eq!(store.get(&key(7)), Some(output.clone()));
+
+        // another key in the same file is a miss, not the wrong result
+        let collision = MemoKey {
+            hash: key(7).hash(),
+            material: "other".to_string(),
+        };
+        assert!(store.get(&collision).is_none());
+        store.put(collision.clone(), &output);
+        assert!(store.get(&key(7)).is_none());
+        assert!(store.get(&collision).is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
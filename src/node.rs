@@ -11,15 +11,25 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use crate::{EngineError, Input, Output};
+use crate::{EngineError, Input, Output, SocketType};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use thiserror::Error;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+pub type NodeId = i64;
+
+/// Key in `Node.data` holding the version of the data layout
+pub const NODE_VERSION_KEY: &str = "_version";
+
+/// Output that receives the error message of a failed worker, or the panic
+/// message under `EngineOptions::catch_panics`. When it is connected the run
+/// carries on from it instead of failing.
+pub const ERROR_OUTPUT_KEY: &str = "error";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum OutputValue {
     String(String),
     Bytes(Vec<u8>),
@@ -39,6 +49,15 @@ impl Display for OutputValue {
 }
 
 impl OutputValue {
+    pub fn socket_type(&self) -> SocketType {
+        match self {
+            OutputValue::String(_) => SocketType::String,
+            OutputValue::Bytes(_) => SocketType::Bytes,
+            OutputValue::I64(_) => SocketType::I64,
+            OutputValue::U64(_) => SocketType::U64,
+        }
+    }
+
     pub fn as_i64(&self) -> Result<i64, EngineError> {
         match self {
             OutputValue::I64(i) => Ok(*i),
@@ -76,12 +95,17 @@ pub struct Node {
     pub name: String,
     #[serde(default)]
     pub data: HashMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub group: Option<i64>,
-    pub position: Option<Vec<f32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<Vec<f64>>,
     #[serde(default)]
     pub inputs: HashMap<String, Input>,
     #[serde(default)]
     pub outputs: HashMap<String, Output>,
+    /// Fields the engine doesn't use, kept so the editor gets them back
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 impl Node {
@@ -97,6 +121,15 @@ impl Node {
             })
             .transpose()
     }
+
+    pub fn version(&self) -> Result<Option<u32>, NodeError> {
+        self.get_data(NODE_VERSION_KEY)
+    }
+
+    pub fn set_version(&mut self, version: u32) {
+        self.data
+            .insert(NODE_VERSION_KEY.to_string(), Value::from(version));
+    }
 }
 
 #[cfg(test)]
@@ -118,6 +151,7 @@ mod test {
             position: None,
             inputs: Default::default(),
             outputs: Default::default(),
+            extra: Default::default(),
         };
 
         let value: i32 = node.get_data("test").unwrap().unwrap();
@@ -0,0 +1,244 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::graph::*;
+use crate::node::*;
+use crate::target::*;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Conflict {
+    /// A connection on `node_id` points at a node that is not in the graph, it is dropped
+    DanglingConnection {
+        node_id: NodeId,
+        port: String,
+        missing_node: NodeId,
+    },
+    /// Both sides list the connection with different `data`, the output side is kept
+    DataMismatch(Connection),
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Conflict::DanglingConnection {
+                node_id,
+                port,
+                missing_node,
+            } => write!(
+                f,
+                "Node {} port `{}` is connected to missing node {}",
+                node_id, port, missing_node
+            ),
+            Conflict::DataMismatch(c) => write!(f, "Connection data differs: {}", c),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NormalizeReport {
+    /// Connections that were only listed on one side and have been mirrored
+    pub repaired: Vec<Connection>,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl NormalizeReport {
+    pub fn is_clean(&self) -> bool {
+        self.repaired.is_empty() && self.conflicts.is_empty()
+    }
+}
+
+impl Graph {
+    /// Makes `Node.inputs` and `Node.outputs` agree: connections listed on only one
+    /// side are copied to the other and connections to missing nodes are dropped.
+    pub fn normalize(&mut self) -> NormalizeReport {
+        let mut report = NormalizeReport::default();
+
+        // drop connections to nodes that don't exist
+        let ids: Vec<NodeId> = self.nodes.keys().copied().collect();
+        for node in self.nodes.values_mut() {
+            for (port, input) in node.inputs.iter_mut() {
+                input.connections.retain(|c| {
+                    let keep = ids.contains(&c.node);
+                    if !keep {
+                        report.conflicts.push(Conflict::DanglingConnection {
+                            node_id: node.id,
+                            port: port.clone(),
+                            missing_node: c.node,
+                        });
+                    }
+                    keep
+                });
+            }
+            for (port, output) in node.outputs.iter_mut() {
+                output.connections.retain(|c| {
+                    let keep = ids.contains(&c.node);
+                    if !keep {
+                        report.conflicts.push(Conflict::DanglingConnection {
+                            node_id: node.id,
+                            port: port.clone(),
+                            missing_node: c.node,
+                        });
+                    }
+                    keep
+                });
+            }
+        }
+
+        let mut from_outputs: HashMap<Connection, Value> = HashMap::new();
+        let mut from_inputs: HashMap<Connection, Value> = HashMap::new();
+        for node in self.nodes.values() {
+            for (name, output) in &node.outputs {
+                for c in &output.connections {
+                    from_outputs.insert(
+                        Connection::new(node.id, name, c.node, &c.input),
+                        c.data.clone(),
+                    );
+                }
+            }
+            for (name, input) in &node.inputs {
+                for c in &input.connections {
+                    from_inputs.insert(
+                        Connection::new(c.node, &c.output, node.id, name),
+                        c.data.clone(),
+                    );
+                }
+            }
+        }
+
+        for (connection, data) in &from_outputs {
+            match from_inputs.get(connection) {
+                None => {
+                    self.nodes
+                        .get_mut(&connection.to)
+                        .unwrap()
+                        .inputs
+                        .entry(connection.input.clone())
+                        .or_insert_with(|| Input {
+                            connections: vec![],
+                        })
+                        .connections
+                        .push(InputConnection {
+                            node: connection.from,
+                            output: connection.output.clone(),
+                            data: data.clone(),
+                        });
+                    report.repaired.push(connection.clone());
+                }
+                Some(input_data) if input_data != data => {
+                    for c in self
+                        .nodes
+                        .get_mut(&connection.to)
+                        .unwrap()
+                        .inputs
+                        .get_mut(&connection.input)
+                        .unwrap()
+                        .connections
+                        .iter_mut()
+                        .filter(|c| c.node == connection.from && c.output == connection.output)
+                    {
+                        c.data = data.clone();
+                    }
+                    report
+                        .conflicts
+                        .push(Conflict::DataMismatch(connection.clone()));
+                }
+                Some(_) => (),
+            }
+        }
+        for (connection, data) in &from_inputs {
+            if !from_outputs.contains_key(connection) {
+                self.nodes
+                    .get_mut(&connection.from)
+                    .unwrap()
+                    .outputs
+                    .entry(connection.output.clone())
+                    .or_insert_with(|| Output {
+                        connections: vec![],
+                    })
+                    .connections
+                    .push(OutputConnection {
+                        node: connection.to,
+                        input: connection.input.clone(),
+                        data: data.clone(),
+                    });
+                report.repaired.push(connection.clone());
+            }
+        }
+
+        report.repaired.sort();
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_normalize() {
+        let mut graph: Graph = serde_json::from_value(json!({
+            "id": "demo@0.1.0",
+            "nodes": {
+                "1": {
+                    "id": 1, "name": "Number",
+                    "outputs": { "num": { "connections": [
+                        { "node": 2, "input": "num", "data": {} },
+                        { "node": 9, "input": "num", "data": {} }
+                    ] } }
+                },
+                "2": {
+                    "id": 2, "name": "Add",
+                    "outputs": { "num": { "connections": [
+                        { "node": 3, "input": "num", "data": { "a": 1 } }
+                    ] } }
+                },
+                "3": {
+                    "id": 3, "name": "Add",
+                    "inputs": {
+                        "num": { "connections": [{ "node": 2, "output": "num", "data": { "a": 2 } }] },
+                        "num2": { "connections": [{ "node": 1, "output": "num", "data": {} }] }
+                    }
+                }
+            }
+        }))
+        .unwrap();
+
+        let report = graph.normalize();
+        assert_eq!(
+            report.repaired,
+            vec![
+                Connection::new(1, "num", 2, "num"),
+                Connection::new(1, "num", 3, "num2")
+            ]
+        );
+        assert_eq!(report.conflicts.len(), 2);
+        assert!(report.conflicts.contains(&Conflict::DanglingConnection {
+            node_id: 1,
+            port: "num".to_string(),
+            missing_node: 9
+        }));
+        assert!(report
+            .conflicts
+            .contains(&Conflict::DataMismatch(Connection::new(2, "num", 3, "num"))));
+        assert_eq!(
+            graph.nodes[&3].inputs["num"].connections[0].data,
+            json!({ "a": 1 })
+        );
+
+        assert!(graph.normalize().is_clean());
+    }
+}
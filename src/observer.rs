@@ -0,0 +1,219 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::engine::EngineError;
+use crate::node::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Why the engine did not run a node
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum SkipReason {
+    /// `node_id` didn't produce `output`, which this node is connected to
+    OutputNotProduced { node_id: NodeId, output: String },
+    /// The node's only `action` input comes from a node that was skipped
+    UpstreamSkipped { node_id: NodeId },
+    /// `node_id` failed and this node depends on it, see `ErrorPolicy::Continue`
+    UpstreamFailed { node_id: NodeId },
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::OutputNotProduced { node_id, output } => {
+                write!(f, "node {} produced no `{}`", node_id, output)
+            }
+            SkipReason::UpstreamSkipped { node_id } => write!(f, "node {} was skipped", node_id),
+            SkipReason::UpstreamFailed { node_id } => write!(f, "node {} failed", node_id),
+        }
+    }
+}
+
+/// Called by the engine as a run progresses. Every method does nothing by
+/// default; observers that record anything need interior mutability.
+pub trait ExecutionObserver {
+    fn run_started(&self, _start_node_id: NodeId) {}
+    fn run_finished(
+        &self,
+        _start_node_id: NodeId,
+        _duration: Duration,
+        _error: Option<&EngineError>,
+    ) {
+    }
+    fn node_started(&self, _node: &Node, _inputs: &HashMap<String, OutputValue>) {}
+    fn node_finished(
+        &self,
+        _node: &Node,
+        _outputs: &HashMap<String, OutputValue>,
+        _duration: Duration,
+    ) {
+    }
+    fn node_skipped(&self, _node: &Node, _reason: &SkipReason) {}
+    fn node_failed(&self, _node: &Node, _error: &EngineError, _duration: Duration) {}
+    /// Attempt number `attempt` failed with `error`, the worker is called again after `delay`
+    fn node_retrying(&self, _node: &Node, _attempt: u32, _error: &EngineError, _delay: Duration) {}
+}
+
+impl<O: ExecutionObserver + ?Sized> ExecutionObserver for Rc<O> {
+    fn run_started(&self, start_node_id: NodeId) {
+        (**self).run_started(start_node_id)
+    }
+
+    fn run_finished(&self, start_node_id: NodeId, duration: Duration, error: Option<&EngineError>) {
+        (**self).run_finished(start_node_id, duration, error)
+    }
+
+    fn node_started(&self, node: &Node, inputs: &HashMap<String, OutputValue>) {
+        (**self).node_started(node, inputs)
+    }
+
+    fn node_finished(
+        &self,
+        node: &Node,
+        outputs: &HashMap<String, OutputValue>,
+        duration: Duration,
+    ) {
+        (**self).node_finished(node, outputs, duration)
+    }
+
+    fn node_skipped(&self, node: &Node, reason: &SkipReason) {
+        (**self).node_skipped(node, reason)
+    }
+
+    fn node_failed(&self, node: &Node, error: &EngineError, duration: Duration) {
+        (**self).node_failed(node, error, duration)
+    }
+
+    fn node_retrying(&self, node: &Node, attempt: u32, error: &EngineError, delay: Duration) {
+        (**self).node_retrying(node, attempt, error, delay)
+    }
+}
+
+/// Fans every call out to a list of observers
+pub(crate) struct Observers<'a>(pub Vec<&'a dyn ExecutionObserver>);
+
+impl<'a> ExecutionObserver for Observers<'a> {
+    fn run_started(&self, start_node_id: NodeId) {
+        self.0.iter().for_each(|o| o.run_started(start_node_id))
+    }
+
+    fn run_finished(&self, start_node_id: NodeId, duration: Duration, error: Option<&EngineError>) {
+        self.0
+            .iter()
+            .for_each(|o| o.run_finished(start_node_id, duration, error))
+    }
+
+    fn node_started(&self, node: &Node, inputs: &HashMap<String, OutputValue>) {
+        self.0.iter().for_each(|o| o.node_started(node, inputs))
+    }
+
+    fn node_finished(
+        &self,
+        node: &Node,
+        outputs: &HashMap<String, OutputValue>,
+        duration: Duration,
+    ) {
+        self.0
+            .iter()
+            .for_each(|o| o.node_finished(node, outputs, duration))
+    }
+
+    fn node_skipped(&self, node: &Node, reason: &SkipReason) {
+        self.0.iter().for_each(|o| o.node_skipped(node, reason))
+    }
+
+    fn node_failed(&self, node: &Node, error: &EngineError, duration: Duration) {
+        self.0
+            .iter()
+            .for_each(|o| o.node_failed(node, error, duration))
+    }
+
+    fn node_retrying(&self, node: &Node, attempt: u32, error: &EngineError, delay: Duration) {
+        self.0
+            .iter()
+            .for_each(|o| o.node_retrying(node, attempt, error, delay))
+    }
+}
+
+/// Reports runs through the `log` crate: runs and failures at `debug` and
+/// `warn`, individual nodes at `trace`
+#[cfg(feature = "log")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogObserver;
+
+#[cfg(feature = "log")]
+impl ExecutionObserver for LogObserver {
+    fn run_started(&self, start_node_id: NodeId) {
+        log::debug!("run started at node {}", start_node_id);
+    }
+
+    fn run_finished(&self, start_node_id: NodeId, duration: Duration, error: Option<&EngineError>) {
+        match error {
+            None => log::debug!("run from node {} finished in {:?}", start_node_id, duration),
+            Some(e) => log::warn!(
+                "run from node {} failed after {:?}: {}",
+                start_node_id,
+                duration,
+                e
+            ),
+        }
+    }
+
+    fn node_started(&self, node: &Node, inputs: &HashMap<String, OutputValue>) {
+        log::trace!("node {} ({}) started with {:?}", node.id, node.name, inputs);
+    }
+
+    fn node_finished(
+        &self,
+        node: &Node,
+        outputs: &HashMap<String, OutputValue>,
+        duration: Duration,
+    ) {
+        log::trace!(
+            "node {} ({}) finished in {:?} with {:?}",
+            node.id,
+            node.name,
+            duration,
+            outputs
+        );
+    }
+
+    fn node_skipped(&self, node: &Node, reason: &SkipReason) {
+        log::trace!("node {} ({}) skipped: {}", node.id, node.name, reason);
+    }
+
+    fn node_failed(&self, node: &Node, error: &EngineError, duration: Duration) {
+        log::warn!(
+            "node {} ({}) failed after {:?}: {}",
+            node.id,
+            node.name,
+            duration,
+            error
+        );
+    }
+
+    fn node_retrying(&self, node: &Node, attempt: u32, error: &EngineError, delay: Duration) {
+        log::debug!(
+            "node {} ({}) attempt {} failed, retrying in {:?}: {}",
+            node.id,
+            node.name,
+            attempt,
+            delay,
+            error
+        );
+    }
+}
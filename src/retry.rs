@@ -0,0 +1,150 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::node::*;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// How the engine waits between retries, replaced in tests to avoid real sleeps
+pub trait Clock {
+    fn sleep(&self, duration: Duration);
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backoff {
+    #[default]
+    None,
+    Fixed(Duration),
+    /// `initial`, doubled after every failed attempt up to `max`
+    Exponential {
+        initial: Duration,
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// Wait after the `attempt`th failed attempt, counting from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        match self {
+            Backoff::None => Duration::ZERO,
+            Backoff::Fixed(delay) => *delay,
+            Backoff::Exponential { initial, max } => initial
+                .checked_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+                .map_or(*max, |delay| delay.min(*max)),
+        }
+    }
+}
+
+type Retryable = Rc<dyn Fn(&anyhow::Error) -> bool>;
+
+/// How often a failing worker is called again with the same inputs
+#[derive(Clone)]
+pub struct RetryPolicy {
+    /// Calls in total, including the first one
+    pub attempts: u32,
+    pub backoff: Backoff,
+    retryable: Option<Retryable>,
+}
+
+impl RetryPolicy {
+    pub fn new(attempts: u32) -> Self {
+        Self {
+            attempts,
+            backoff: Backoff::None,
+            retryable: None,
+        }
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Only errors `retryable` returns true for are retried, by default all are.
+    /// It is given the error the worker returned.
+    pub fn retry_if<F: Fn(&anyhow::Error) -> bool + 'static>(mut self, retryable: F) -> Self {
+        self.retryable = Some(Rc::new(retryable));
+        self
+    }
+
+    pub fn is_retryable(&self, error: &anyhow::Error) -> bool {
+        self.retryable.as_ref().is_none_or(|r| r(error))
+    }
+}
+
+impl Debug for RetryPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("attempts", &self.attempts)
+            .field("backoff", &self.backoff)
+            .field("retryable", &self.retryable.is_some())
+            .finish()
+    }
+}
+
+/// Retry policies by worker name, with per node overrides
+#[derive(Clone, Debug, Default)]
+pub struct Retries {
+    workers: HashMap<String, RetryPolicy>,
+    nodes: HashMap<NodeId, RetryPolicy>,
+}
+
+impl Retries {
+    pub fn worker(mut self, name: &str, policy: RetryPolicy) -> Self {
+        self.workers.insert(name.to_string(), policy);
+        self
+    }
+
+    pub fn node(mut self, node_id: NodeId, policy: RetryPolicy) -> Self {
+        self.nodes.insert(node_id, policy);
+        self
+    }
+
+    pub fn policy(&self, node: &Node) -> Option<&RetryPolicy> {
+        self.nodes
+            .get(&node.id)
+            .or_else(|| self.workers.get(&node.name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let ms = Duration::from_millis;
+        assert_eq!(Backoff::None.delay(3), Duration::ZERO);
+        assert_eq!(Backoff::Fixed(ms(5)).delay(3), ms(5));
+        let backoff = Backoff::Exponential {
+            initial: ms(10),
+            max: ms(50),
+        };
+        assert_eq!(backoff.delay(1), ms(10));
+        assert_eq!(backoff.delay(2), ms(20));
+        assert_eq!(backoff.delay(3), ms(40));
+        assert_eq!(backoff.delay(4), ms(50));
+        assert_eq!(backoff.delay(100), ms(50));
+    }
+}
@@ -0,0 +1,442 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::engine::EngineError;
+use crate::graph::Connection;
+use crate::node::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use thiserror::Error;
+
+/// Type of the values travelling over a port, one per `OutputValue` variant plus
+/// `Action` for control flow and `Any`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum SocketType {
+    Any,
+    Action,
+    String,
+    Bytes,
+    I64,
+    U64,
+}
+
+impl Display for SocketType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocketType::Any => write!(f, "any"),
+            SocketType::Action => write!(f, "action"),
+            SocketType::String => write!(f, "string"),
+            SocketType::Bytes => write!(f, "bytes"),
+            SocketType::I64 => write!(f, "i64"),
+            SocketType::U64 => write!(f, "u64"),
+        }
+    }
+}
+
+impl SocketType {
+    /// Whether `value` may travel over a socket of this type. An action only
+    /// signals that the flow goes on, so it carries whatever the worker emits.
+    pub fn accepts(&self, value: &OutputValue) -> bool {
+        match self {
+            SocketType::Any | SocketType::Action => true,
+            socket => *socket == value.socket_type(),
+        }
+    }
+}
+
+/// Which output sockets may be connected to which input sockets. Equal types and
+/// `Any` on either side are always allowed, other pairs have to be added.
+#[derive(Clone, Debug, Default)]
+pub struct SocketRules {
+    allowed: HashSet<(SocketType, SocketType)>,
+}
+
+impl SocketRules {
+    pub fn allow(mut self, output: SocketType, input: SocketType) -> Self {
+        self.allowed.insert((output, input));
+        self
+    }
+
+    pub fn compatible(&self, output: SocketType, input: SocketType) -> bool {
+        output == input
+            || output == SocketType::Any
+            || input == SocketType::Any
+            || self.allowed.contains(&(output, input))
+    }
+}
+
+/// Type of a control field stored in `node.data`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlType {
+    Any,
+    String,
+    Number,
+    Integer,
+    Boolean,
+    Object,
+    Array,
+}
+
+impl ControlType {
+    pub fn accepts(&self, value: &Value) -> bool {
+        match self {
+            ControlType::Any => true,
+            ControlType::String => value.is_string(),
+            ControlType::Number => value.is_number(),
+            ControlType::Integer => value.is_i64() || value.is_u64(),
+            ControlType::Boolean => value.is_boolean(),
+            ControlType::Object => value.is_object(),
+            ControlType::Array => value.is_array(),
+        }
+    }
+}
+
+impl Display for ControlType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PortSchema {
+    pub name: String,
+    pub socket: SocketType,
+    pub required: bool,
+}
+
+impl PortSchema {
+    pub fn new(name: &str, socket: SocketType) -> Self {
+        Self {
+            name: name.to_string(),
+            socket,
+            required: true,
+        }
+    }
+
+    pub fn optional(mut self) -> Self {
+        self.required = false;
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ControlSchema {
+    pub name: String,
+    pub kind: ControlType,
+    pub required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl ControlSchema {
+    pub fn new(name: &str, kind: ControlType) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            required: true,
+            default: None,
+            description: None,
+        }
+    }
+
+    pub fn optional(mut self) -> Self {
+        self.required = false;
+        self
+    }
+
+    /// A control with a default is never missing
+    pub fn default_value(mut self, value: Value) -> Self {
+        self.default = Some(value);
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+}
+
+/// What a worker expects of its nodes. A schema without any ports or controls is
+/// treated as undeclared and is not validated.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ComponentSchema {
+    pub name: String,
+    /// Filled in from `Worker::version` by the registry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub inputs: Vec<PortSchema>,
+    #[serde(default)]
+    pub outputs: Vec<PortSchema>,
+    #[serde(default)]
+    pub controls: Vec<ControlSchema>,
+}
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum SchemaError {
+    #[error("Node[{node_id}]: no worker for component `{name}`")]
+    UnknownComponent { node_id: NodeId, name: String },
+    #[error("Node[{node_id}]: required input `{port}` is not connected")]
+    MissingInput { node_id: NodeId, port: String },
+    #[error("Node[{node_id}]: unknown input `{port}`")]
+    UnknownInput { node_id: NodeId, port: String },
+    #[error("Node[{node_id}]: unknown output `{port}`")]
+    UnknownOutput { node_id: NodeId, port: String },
+    #[error("Node[{node_id}]: required control `{control}` is missing")]
+    MissingControl { node_id: NodeId, control: String },
+    #[error("Node[{node_id}]: control `{control}` should be {expected}, got {actual}")]
+    InvalidControl {
+        node_id: NodeId,
+        control: String,
+        expected: ControlType,
+        actual: Value,
+    },
+    #[error("Node[{node_id}]: input `{port}` should be {expected}, got {actual}")]
+    InputTypeMismatch {
+        node_id: NodeId,
+        port: String,
+        expected: SocketType,
+        actual: String,
+    },
+    #[error("Connection {connection}: {output} output can't be connected to {input} input")]
+    SocketMismatch {
+        connection: Connection,
+        output: SocketType,
+        input: SocketType,
+    },
+}
+
+impl ComponentSchema {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn category(mut self, category: &str) -> Self {
+        self.category = Some(category.to_string());
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    pub fn input(mut self, port: PortSchema) -> Self {
+        self.inputs.push(port);
+        self
+    }
+
+    pub fn output(mut self, port: PortSchema) -> Self {
+        self.outputs.push(port);
+        self
+    }
+
+    pub fn control(mut self, control: ControlSchema) -> Self {
+        self.controls.push(control);
+        self
+    }
+
+    pub fn is_declared(&self) -> bool {
+        !(self.inputs.is_empty() && self.outputs.is_empty() && self.controls.is_empty())
+    }
+
+    pub fn get_input(&self, name: &str) -> Option<&PortSchema> {
+        self.inputs.iter().find(|p| p.name == name)
+    }
+
+    pub fn get_output(&self, name: &str) -> Option<&PortSchema> {
+        self.outputs.iter().find(|p| p.name == name)
+    }
+
+    /// Checks the values a worker produced for `node_id` against the socket
+    /// types of the declared outputs, outputs it doesn't declare are not checked
+    pub fn check_outputs(
+        &self,
+        node_id: NodeId,
+        output: &HashMap<String, OutputValue>,
+    ) -> Result<(), EngineError> {
+        for (name, value) in output {
+            if let Some(port) = self.get_output(name) {
+                if !port.socket.accepts(value) {
+                    return Err(EngineError::OutputTypeMismatch {
+                        node_id,
+                        port: name.clone(),
+                        expected: port.socket,
+                        actual: value.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks ports and controls of `node` against this schema
+    pub fn validate(&self, node: &Node) -> Vec<SchemaError> {
+        let mut errors = vec![];
+        if !self.is_declared() {
+            return errors;
+        }
+        for port in &self.inputs {
+            let connected = node
+                .inputs
+                .get(&port.name)
+                .map(|i| !i.connections.is_empty())
+                .unwrap_or(false);
+            if port.required && !connected {
+                errors.push(SchemaError::MissingInput {
+                    node_id: node.id,
+                    port: port.name.clone(),
+                });
+            }
+        }
+        let mut inputs: Vec<&String> = node.inputs.keys().collect();
+        inputs.sort();
+        for name in inputs {
+            if self.get_input(name).is_none() {
+                errors.push(SchemaError::UnknownInput {
+                    node_id: node.id,
+                    port: name.clone(),
+                });
+            }
+        }
+        let mut outputs: Vec<&String> = node.outputs.keys().collect();
+        outputs.sort();
+        for name in outputs {
+            if self.get_output(name).is_none() {
+                errors.push(SchemaError::UnknownOutput {
+                    node_id: node.id,
+                    port: name.clone(),
+                });
+            }
+        }
+        for control in &self.controls {
+            match node.data.get(&control.name) {
+                None | Some(Value::Null) => {
+                    if control.required && control.default.is_none() {
+                        errors.push(SchemaError::MissingControl {
+                            node_id: node.id,
+                            control: control.name.clone(),
+                        });
+                    }
+                }
+                Some(value) if !control.kind.accepts(value) => {
+                    errors.push(SchemaError::InvalidControl {
+                        node_id: node.id,
+                        control: control.name.clone(),
+                        expected: control.kind,
+                        actual: value.clone(),
+                    })
+                }
+                Some(_) => (),
+            }
+        }
+        errors
+    }
+
+    /// Fills controls missing from `node.data` with their defaults
+    pub fn apply_defaults(&self, node: &mut Node) {
+        for control in &self.controls {
+            if let Some(default) = &control.default {
+                node.data
+                    .entry(control.name.clone())
+                    .or_insert_with(|| default.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GraphBuilder;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_accepts() {
+        assert!(SocketType::I64.accepts(&OutputValue::I64(1)));
+        assert!(!SocketType::I64.accepts(&OutputValue::U64(1)));
+        assert!(SocketType::Any.accepts(&OutputValue::Bytes(vec![])));
+        assert!(SocketType::Action.accepts(&OutputValue::String("go".to_string())));
+    }
+
+    #[test]
+    fn test_socket_rules() {
+        let rules = SocketRules::default().allow(SocketType::U64, SocketType::I64);
+        assert!(rules.compatible(SocketType::I64, SocketType::I64));
+        assert!(rules.compatible(SocketType::Any, SocketType::Bytes));
+        assert!(rules.compatible(SocketType::String, SocketType::Any));
+        assert!(rules.compatible(SocketType::U64, SocketType::I64));
+        assert!(!rules.compatible(SocketType::I64, SocketType::U64));
+        assert!(!rules.compatible(SocketType::String, SocketType::I64));
+
+        assert!(SocketType::I64.accepts(&OutputValue::I64(1)));
+        assert!(!SocketType::I64.accepts(&OutputValue::String("1".to_string())));
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = ComponentSchema::new("Add")
+            .category("Math")
+            .input(PortSchema::new("num", SocketType::I64))
+            .input(PortSchema::new("num2", SocketType::I64).optional())
+            .output(PortSchema::new("num", SocketType::I64))
+            .control(ControlSchema::new("scale", ControlType::Integer).default_value(json!(1)))
+            .control(ControlSchema::new("label", ControlType::String));
+
+        let mut builder = GraphBuilder::default();
+        let a = builder.add_node("Number", HashMap::new());
+        let add = builder.add_node("Add", HashMap::from([("scale".to_string(), json!("x"))]));
+        builder.connect(a, "num", add, "num2").unwrap();
+        let mut nodes = builder.build_nodes();
+
+        assert_eq!(
+            schema.validate(&nodes[&add]),
+            vec![
+                SchemaError::MissingInput {
+                    node_id: add,
+                    port: "num".to_string()
+                },
+                SchemaError::InvalidControl {
+                    node_id: add,
+                    control: "scale".to_string(),
+                    expected: ControlType::Integer,
+                    actual: json!("x")
+                },
+                SchemaError::MissingControl {
+                    node_id: add,
+                    control: "label".to_string()
+                },
+            ]
+        );
+
+        let node = nodes.get_mut(&add).unwrap();
+        node.data.remove("scale");
+        schema.apply_defaults(node);
+        assert_eq!(node.data["scale"], json!(1));
+        assert!(ComponentSchema::new("Empty").validate(node).is_empty());
+    }
+}
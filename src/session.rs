@@ -0,0 +1,145 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::engine::{Engine, EngineError, OutputCache, RunOptions};
+use crate::graph::*;
+use crate::node::*;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+/// Keeps node outputs between runs. Changing a node's data or connections only
+/// drops the cached outputs of that node and everything downstream of it, so the
+/// next `run` recomputes just those.
+pub struct Session<'a, TContext> {
+    engine: &'a Engine<TContext>,
+    graph: Graph,
+    start_node_id: NodeId,
+    cache: OutputCache,
+    options: RunOptions<'a>,
+}
+
+impl<'a, TContext> Session<'a, TContext> {
+    pub fn new(
+        engine: &'a Engine<TContext>,
+        nodes: HashMap<NodeId, Node>,
+        start_node_id: NodeId,
+    ) -> Self {
+        Self {
+            engine,
+            graph: Graph {
+                nodes,
+                ..Default::default()
+            },
+            start_node_id,
+            cache: OutputCache::new(),
+            options: RunOptions::new(),
+        }
+    }
+
+    /// Makes every run with `options`, see `Engine::run_with`
+    pub fn with_options(mut self, options: RunOptions<'a>) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn nodes(&self) -> &HashMap<NodeId, Node> {
+        &self.graph.nodes
+    }
+
+    pub fn run(&mut self, context: &TContext) -> Result<HashMap<String, OutputValue>, EngineError> {
+        self.engine.run(
+            context,
+            &self.graph.nodes,
+            self.start_node_id,
+            &mut self.cache,
+            &self.options,
+        )
+    }
+
+    /// Outputs of `id` from the last run, if they are still valid
+    pub fn cached(&self, id: NodeId) -> Option<&HashMap<String, OutputValue>> {
+        self.cache.get(&id).map(|o| o.as_ref())
+    }
+
+    pub fn update_node_data(
+        &mut self,
+        id: NodeId,
+        key: &str,
+        value: Value,
+    ) -> Result<(), GraphError> {
+        self.graph
+            .nodes
+            .get_mut(&id)
+            .ok_or(GraphError::NodeNotFound(id))?
+            .data
+            .insert(key.to_string(), value);
+        self.invalidate(id);
+        Ok(())
+    }
+
+    pub fn connect(
+        &mut self,
+        from: NodeId,
+        output: &str,
+        to: NodeId,
+        input: &str,
+    ) -> Result<(), GraphError> {
+        self.graph.connect(from, output, to, input)?;
+        self.invalidate(to);
+        Ok(())
+    }
+
+    pub fn disconnect(
+        &mut self,
+        from: NodeId,
+        output: &str,
+        to: NodeId,
+        input: &str,
+    ) -> Result<(), GraphError> {
+        self.graph.disconnect(from, output, to, input)?;
+        self.invalidate(to);
+        Ok(())
+    }
+
+    /// Drops the cached outputs of `id` and of every node that depends on it
+    pub fn invalidate(&mut self, id: NodeId) {
+        let mut pending = vec![id];
+        let mut seen = HashSet::new();
+        while let Some(id) = pending.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            self.cache.remove(&id);
+            for node in self.graph.nodes.values() {
+                let reads = node
+                    .inputs
+                    .values()
+                    .any(|i| i.connections.iter().any(|c| c.node == id));
+                if reads {
+                    pending.push(node.id);
+                }
+            }
+            if let Some(node) = self.graph.nodes.get(&id) {
+                pending.extend(
+                    node.outputs
+                        .values()
+                        .flat_map(|o| o.connections.iter().map(|c| c.node)),
+                );
+            }
+        }
+    }
+
+    pub fn invalidate_all(&mut self) {
+        self.cache.clear();
+    }
+}
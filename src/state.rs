@@ -0,0 +1,200 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::node::*;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::PathBuf;
+
+/// Which node a piece of persistent state belongs to
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateKey {
+    pub graph_key: String,
+    pub node_id: NodeId,
+}
+
+impl StateKey {
+    pub fn new(graph_key: &str, node_id: NodeId) -> Self {
+        Self {
+            graph_key: graph_key.to_string(),
+            node_id,
+        }
+    }
+}
+
+impl Display for StateKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.graph_key, self.node_id)
+    }
+}
+
+/// State of every node, by graph key and then node id
+pub type NodeStates = HashMap<String, HashMap<NodeId, Value>>;
+
+/// Where the state of stateful workers is kept between runs
+pub trait StateStore {
+    fn get(&self, key: &StateKey) -> Result<Option<Value>>;
+    fn put(&self, key: StateKey, value: Value) -> Result<()>;
+    fn remove(&self, key: &StateKey) -> Result<()>;
+}
+
+/// Keeps the state in memory. `states` and `from_states` move it in and out,
+/// e.g. to save it along with the graph.
+#[derive(Debug, Default)]
+pub struct MemoryStateStore {
+    states: RefCell<NodeStates>,
+}
+
+impl MemoryStateStore {
+    pub fn from_states(states: NodeStates) -> Self {
+        Self {
+            states: RefCell::new(states),
+        }
+    }
+
+    pub fn states(&self) -> NodeStates {
+        self.states.borrow().clone()
+    }
+
+    /// Removes the state of every node under `graph_key`
+    pub fn clear_graph(&self, graph_key: &str) {
+        self.states.borrow_mut().remove(graph_key);
+    }
+}
+
+impl StateStore for MemoryStateStore {
+    fn get(&self, key: &StateKey) -> Result<Option<Value>> {
+        Ok(self
+            .states
+            .borrow()
+            .get(&key.graph_key)
+            .and_then(|nodes| nodes.get(&key.node_id))
+            .cloned())
+    }
+
+    fn put(&self, key: StateKey, value: Value) -> Result<()> {
+        self.states
+            .borrow_mut()
+            .entry(key.graph_key)
+            .or_default()
+            .insert(key.node_id, value);
+        Ok(())
+    }
+
+    fn remove(&self, key: &StateKey) -> Result<()> {
+        let mut states = self.states.borrow_mut();
+        if let Some(nodes) = states.get_mut(&key.graph_key) {
+            nodes.remove(&key.node_id);
+            if nodes.is_empty() {
+                states.remove(&key.graph_key);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the state of all nodes in one JSON file, written again on every change
+pub struct FileStateStore {
+    path: PathBuf,
+    memory: MemoryStateStore,
+}
+
+impl FileStateStore {
+    /// Loads the states already in the file at `path`, if there is one
+    pub fn new<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path = path.into();
+        let states = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => NodeStates::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            memory: MemoryStateStore::from_states(states),
+        })
+    }
+
+    pub fn states(&self) -> NodeStates {
+        self.memory.states()
+    }
+
+    fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(&*self.memory.states.borrow())?;
+        // write then rename so a failed write leaves the old file intact
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl StateStore for FileStateStore {
+    fn get(&self, key: &StateKey) -> Result<Option<Value>> {
+        self.memory.get(key)
+    }
+
+    fn put(&self, key: StateKey, value: Value) -> Result<()> {
+        self.memory.put(key, value)?;
+        self.save()
+    }
+
+    fn remove(&self, key: &StateKey) -> Result<()> {
+        self.memory.remove(key)?;
+        self.save()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryStateStore::default();
+        let key = StateKey::new("doc-1", 1);
+        assert_eq!(store.get(&key).unwrap(), None);
+        store.put(key.clone(), json!(1)).unwrap();
+        store.put(StateKey::new("doc-2", 1), json!(2)).unwrap();
+        assert_eq!(store.get(&key).unwrap(), Some(json!(1)));
+
+        let copy = MemoryStateStore::from_states(store.states());
+        assert_eq!(copy.get(&key).unwrap(), Some(json!(1)));
+        store.remove(&key).unwrap();
+        assert_eq!(store.get(&key).unwrap(), None);
+        assert_eq!(store.states().len(), 1);
+        store.clear_graph("doc-2");
+        assert!(store.states().is_empty());
+    }
+
+    #[test]
+    fn test_file_store() {
+        let path = std::env::temp_dir().join(format!("d3ne-state-{}.json", std::process::id()));
+        let key = StateKey::new("doc-1", 3);
+        let store = FileStateStore::new(&path).unwrap();
+        assert_eq!(store.get(&key).unwrap(), None);
+        store.put(key.clone(), json!({ "total": 5 })).unwrap();
+
+        let reopened = FileStateStore::new(&path).unwrap();
+        assert_eq!(reopened.get(&key).unwrap(), Some(json!({ "total": 5 })));
+        reopened.remove(&key).unwrap();
+        assert!(FileStateStore::new(&path).unwrap().states().is_empty());
+        fs::remove_file(path).unwrap();
+    }
+}
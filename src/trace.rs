@@ -0,0 +1,167 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::engine::EngineError;
+use crate::node::*;
+use crate::observer::*;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum NodeStatus {
+    Ran,
+    Skipped,
+    Failed,
+    #[default]
+    NeverReached,
+}
+
+/// A failed call to a worker that was retried
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryAttempt {
+    pub attempt: u32,
+    pub error: String,
+    pub delay_micros: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeTrace {
+    pub name: String,
+    pub status: NodeStatus,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub inputs: HashMap<String, OutputValue>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub outputs: HashMap<String, OutputValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_micros: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_reason: Option<SkipReason>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retries: Vec<RetryAttempt>,
+}
+
+/// What happened to every node of a run, keyed by node id so the editor can
+/// overlay it on the canvas
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionTrace {
+    pub start_node_id: NodeId,
+    /// Nodes in the order the engine ran or skipped them
+    pub order: Vec<NodeId>,
+    pub nodes: BTreeMap<NodeId, NodeTrace>,
+    pub duration_micros: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ExecutionTrace {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+/// Observer that builds an `ExecutionTrace`
+#[derive(Default)]
+pub struct TraceRecorder(RefCell<ExecutionTrace>);
+
+impl TraceRecorder {
+    /// The recorded trace, with every node of `nodes` that wasn't visited marked
+    /// as never reached
+    pub fn finish(self, nodes: &HashMap<NodeId, Node>) -> ExecutionTrace {
+        let mut trace = self.0.into_inner();
+        for node in nodes.values() {
+            trace.nodes.entry(node.id).or_insert_with(|| NodeTrace {
+                name: node.name.clone(),
+                ..Default::default()
+            });
+        }
+        trace
+    }
+
+    fn update<F: FnOnce(&mut NodeTrace)>(&self, node: &Node, f: F) {
+        let mut trace = self.0.borrow_mut();
+        let entry = trace.nodes.entry(node.id).or_insert_with(|| NodeTrace {
+            name: node.name.clone(),
+            ..Default::default()
+        });
+        f(entry)
+    }
+}
+
+impl ExecutionObserver for TraceRecorder {
+    fn run_started(&self, start_node_id: NodeId) {
+        self.0.borrow_mut().start_node_id = start_node_id;
+    }
+
+    fn run_finished(
+        &self,
+        _start_node_id: NodeId,
+        duration: Duration,
+        error: Option<&EngineError>,
+    ) {
+        let mut trace = self.0.borrow_mut();
+        trace.duration_micros = duration.as_micros() as u64;
+        trace.error = error.map(|e| e.to_string());
+    }
+
+    fn node_started(&self, node: &Node, inputs: &HashMap<String, OutputValue>) {
+        self.0.borrow_mut().order.push(node.id);
+        self.update(node, |t| t.inputs = inputs.clone());
+    }
+
+    fn node_finished(
+        &self,
+        node: &Node,
+        outputs: &HashMap<String, OutputValue>,
+        duration: Duration,
+    ) {
+        self.update(node, |t| {
+            t.status = NodeStatus::Ran;
+            t.outputs = outputs.clone();
+            t.duration_micros = Some(duration.as_micros() as u64);
+        });
+    }
+
+    fn node_skipped(&self, node: &Node, reason: &SkipReason) {
+        self.0.borrow_mut().order.push(node.id);
+        self.update(node, |t| {
+            t.status = NodeStatus::Skipped;
+            t.skip_reason = Some(reason.clone());
+        });
+    }
+
+    fn node_failed(&self, node: &Node, error: &EngineError, duration: Duration) {
+        self.update(node, |t| {
+            t.status = NodeStatus::Failed;
+            t.error = Some(error.to_string());
+            t.duration_micros = Some(duration.as_micros() as u64);
+        });
+    }
+
+    fn node_retrying(&self, node: &Node, attempt: u32, error: &EngineError, delay: Duration) {
+        self.update(node, |t| {
+            t.retries.push(RetryAttempt {
+                attempt,
+                error: error.to_string(),
+                delay_micros: delay.as_micros() as u64,
+            })
+        });
+    }
+}
@@ -0,0 +1,156 @@
+// Original Copyright © 2021 lemonxah
+// Modified Copyright © 2022 stringhandler
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::graph::Graph;
+use anyhow::Result;
+use semver::{Version, VersionReq};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum VersionError {
+    #[error("Expected `name@version`, got `{0}`")]
+    MissingName(String),
+    #[error("Invalid version `{version}`: {source}")]
+    InvalidVersion {
+        version: String,
+        source: semver::Error,
+    },
+}
+
+fn parse_version(version: &str) -> Result<Version, VersionError> {
+    Version::parse(version).map_err(|source| VersionError::InvalidVersion {
+        version: version.to_string(),
+        source,
+    })
+}
+
+/// A `name@version` id as used by Rete for both the engine and the graph
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GraphVersion {
+    pub name: String,
+    pub version: Version,
+}
+
+impl GraphVersion {
+    /// The versions a graph may have to run on an engine of this version: the same
+    /// minor for `0.x` releases, the same major otherwise, and never newer than
+    /// the engine itself.
+    pub fn default_requirement(&self) -> VersionReq {
+        let lowest = if self.version.major == 0 {
+            format!("0.{}.0", self.version.minor)
+        } else {
+            format!("{}.0.0", self.version.major)
+        };
+        let req = format!(">={}, <={}", lowest, self.version);
+        VersionReq::parse(&req).expect("valid version requirement")
+    }
+}
+
+impl FromStr for GraphVersion {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<Self, VersionError> {
+        let (name, version) = s
+            .rsplit_once('@')
+            .ok_or_else(|| VersionError::MissingName(s.to_string()))?;
+        Ok(Self {
+            name: name.to_string(),
+            version: parse_version(version)?,
+        })
+    }
+}
+
+impl Display for GraphVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.name, self.version)
+    }
+}
+
+type MigrationFn = Box<dyn Fn(&mut Graph) -> Result<()>>;
+
+pub struct Migration {
+    pub from: Version,
+    pub to: Version,
+    migrate: MigrationFn,
+}
+
+impl Migration {
+    pub fn apply(&self, graph: &mut Graph) -> Result<()> {
+        (self.migrate)(graph)
+    }
+}
+
+/// Steps that upgrade stored graphs from one version to the next
+#[derive(Default)]
+pub struct Migrations(Vec<Migration>);
+
+impl Migrations {
+    pub fn add<F>(&mut self, from: &str, to: &str, migrate: F) -> Result<&mut Self, VersionError>
+    where
+        F: Fn(&mut Graph) -> Result<()> + 'static,
+    {
+        self.0.push(Migration {
+            from: parse_version(from)?,
+            to: parse_version(to)?,
+            migrate: Box::new(migrate),
+        });
+        Ok(self)
+    }
+
+    /// The migration to run on a graph at `version`, never going past `target`
+    pub fn next(&self, version: &Version, target: &Version) -> Option<&Migration> {
+        self.0
+            .iter()
+            .filter(|m| &m.from == version && m.to > m.from && &m.to <= target)
+            .max_by(|a, b| a.to.cmp(&b.to))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        let version: GraphVersion = "demo@0.1.1".parse().unwrap();
+        assert_eq!(version.name, "demo");
+        assert_eq!(version.version, Version::new(0, 1, 1));
+        assert_eq!(version.to_string(), "demo@0.1.1");
+        assert!(matches!(
+            "demo".parse::<GraphVersion>(),
+            Err(VersionError::MissingName(id)) if id == "demo"
+        ));
+        assert!(matches!(
+            "demo@one".parse::<GraphVersion>(),
+            Err(VersionError::InvalidVersion { version, .. }) if version == "one"
+        ));
+        assert!(Migrations::default()
+            .add("0.1", "0.2.0", |_| Ok(()))
+            .is_err());
+
+        let req = version.default_requirement();
+        assert!(req.matches(&Version::new(0, 1, 0)));
+        assert!(req.matches(&Version::new(0, 1, 1)));
+        assert!(!req.matches(&Version::new(0, 1, 9)));
+        assert!(!req.matches(&Version::new(0, 2, 0)));
+
+        let req = "demo@1.0.0"
+            .parse::<GraphVersion>()
+            .unwrap()
+            .default_requirement();
+        assert!(req.matches(&Version::new(1, 0, 0)));
+        assert!(!req.matches(&Version::new(1, 7, 0)));
+    }
+}
@@ -11,21 +11,98 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+use crate::catalog::Catalog;
+use crate::graph::Connection;
+use crate::invocation::{Invocation, RunState};
+use crate::layer::{Layer, Next};
 use crate::node::*;
+use crate::schema::*;
 use anyhow::Result;
-use std::collections::HashMap;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum WorkerError {
-    #[error("Worker Not Found: `{0}`")]
-    WorkerNotFound(String),
-    #[error("Node[{0}]: {1}")]
-    NodeRunError(i64, anyhow::Error),
+    #[error("Node[{node_id}]: worker not found: `{worker}`")]
+    WorkerNotFound { node_id: i64, worker: String },
+    #[error("Node[{node_id}] `{worker}`: {source}")]
+    NodeRunError {
+        node_id: i64,
+        worker: String,
+        source: anyhow::Error,
+    },
+    #[error("Node[{node_id}]: {source}")]
+    InvalidNodeData { node_id: i64, source: NodeError },
+    #[error("Node[{node_id}] `{worker}` panicked: {message}")]
+    Panicked {
+        node_id: i64,
+        worker: String,
+        message: String,
+    },
+    #[error("Node[{node_id}]: worker `{worker}` has no implementation or migration for version {version}")]
+    VersionNotSupported {
+        node_id: i64,
+        worker: String,
+        version: u32,
+    },
+    #[error("Node[{node_id}]: migrating data from version {from} failed: {source}")]
+    MigrationFailed {
+        node_id: i64,
+        from: u32,
+        source: anyhow::Error,
+    },
+}
+
+impl WorkerError {
+    pub fn node_id(&self) -> Option<i64> {
+        match self {
+            WorkerError::WorkerNotFound { node_id, .. }
+            | WorkerError::NodeRunError { node_id, .. }
+            | WorkerError::InvalidNodeData { node_id, .. }
+            | WorkerError::MigrationFailed { node_id, .. }
+            | WorkerError::Panicked { node_id, .. }
+            | WorkerError::VersionNotSupported { node_id, .. } => Some(*node_id),
+        }
+    }
+
+    /// Name of the worker involved, when the error names one
+    pub fn worker(&self) -> Option<&str> {
+        match self {
+            WorkerError::WorkerNotFound { worker, .. }
+            | WorkerError::NodeRunError { worker, .. }
+            | WorkerError::Panicked { worker, .. }
+            | WorkerError::VersionNotSupported { worker, .. } => Some(worker),
+            _ => None,
+        }
+    }
 }
 
 pub trait Worker<TContext> {
     fn name(&self) -> &str;
+    /// Version of the `node.data` layout this worker understands
+    fn version(&self) -> u32 {
+        1
+    }
+    /// A pure worker's outputs depend only on `node.data` and its inputs, which
+    /// lets the engine memoize them
+    fn pure(&self) -> bool {
+        false
+    }
+    /// Ports and controls of the component, undeclared unless overridden
+    fn schema(&self) -> ComponentSchema {
+        ComponentSchema::new(self.name())
+    }
+    /// What the engine calls. By default this is `work` with the invocation's
+    /// context; override it as well to use the run state.
+    fn invoke(
+        &self,
+        invocation: &Invocation<'_, TContext>,
+        node: &Node,
+        input_data: HashMap<String, OutputValue>,
+    ) -> Result<HashMap<String, OutputValue>> {
+        self.work(invocation.context(), node, input_data)
+    }
     fn work(
         &self,
         context: &TContext,
@@ -34,34 +111,216 @@ pub trait Worker<TContext> {
     ) -> Result<HashMap<String, OutputValue>>;
 }
 
-pub struct Workers<TContext>(HashMap<String, Box<dyn Worker<TContext>>>);
+type DataMigrationFn = Box<dyn Fn(&mut HashMap<String, Value>) -> Result<()>>;
+
+struct DataMigration {
+    from: u32,
+    to: u32,
+    migrate: DataMigrationFn,
+}
+
+pub struct Workers<TContext> {
+    workers: HashMap<String, BTreeMap<u32, Box<dyn Worker<TContext>>>>,
+    migrations: HashMap<String, Vec<DataMigration>>,
+    layers: Vec<Box<dyn Layer<TContext>>>,
+}
 
 impl<TContext> Workers<TContext> {
+    /// The implementation for the node's version is used, or `node.data` is
+    /// migrated forward until one is found. Nodes without a version were saved
+    /// before versioning and count as version 1, the default of `Worker::version`.
     pub fn call(
         &self,
         name: &str,
         context: &TContext,
         node: &Node,
         input: HashMap<String, OutputValue>,
-    ) -> Result<HashMap<String, OutputValue>> {
-        self.0
+    ) -> Result<HashMap<String, OutputValue>, WorkerError> {
+        let state = RunState::default();
+        self.invoke(
+            name,
+            &Invocation::new(context, &state, node.id),
+            node,
+            input,
+        )
+    }
+
+    /// Like `call`, with the run state of `invocation`
+    pub fn invoke(
+        &self,
+        name: &str,
+        invocation: &Invocation<'_, TContext>,
+        node: &Node,
+        input: HashMap<String, OutputValue>,
+    ) -> Result<HashMap<String, OutputValue>, WorkerError> {
+        let versions = self
+            .workers
+            .get(name)
+            .ok_or_else(|| WorkerError::WorkerNotFound {
+                node_id: node.id,
+                worker: name.into(),
+            })?;
+        let version = node
+            .version()
+            .map_err(|source| WorkerError::InvalidNodeData {
+                node_id: node.id,
+                source,
+            })?;
+        let mut version = version.unwrap_or(1);
+        let mut migrated: Option<Node> = None;
+        loop {
+            if let Some(worker) = versions.get(&version) {
+                return self.run(
+                    worker.as_ref(),
+                    invocation,
+                    migrated.as_ref().unwrap_or(node),
+                    input,
+                );
+            }
+            let migration = self
+                .migrations
+                .get(name)
+                .and_then(|m| m.iter().find(|m| m.from == version && m.to > m.from))
+                .ok_or_else(|| WorkerError::VersionNotSupported {
+                    node_id: node.id,
+                    worker: name.into(),
+                    version,
+                })?;
+            let node = migrated.get_or_insert_with(|| node.clone());
+            (migration.migrate)(&mut node.data).map_err(|source| WorkerError::MigrationFailed {
+                node_id: node.id,
+                from: version,
+                source,
+            })?;
+            node.set_version(migration.to);
+            version = migration.to;
+        }
+    }
+
+    /// True when every implementation of `name` is pure
+    pub fn is_pure(&self, name: &str) -> bool {
+        self.workers
+            .get(name)
+            .map(|versions| versions.values().all(|w| w.pure()))
+            .unwrap_or(false)
+    }
+
+    /// Version of the newest implementation of `name`
+    pub fn version(&self, name: &str) -> Option<u32> {
+        self.workers
             .get(name)
-            .map(|worker| {
-                worker
-                    .work(context, node, input)
-                    .map_err(|e| anyhow!(WorkerError::NodeRunError(node.id, e)))
+            .and_then(|versions| versions.keys().next_back())
+            .copied()
+    }
+
+    /// Schema of the newest implementation of `name`
+    pub fn schema(&self, name: &str) -> Option<ComponentSchema> {
+        self.workers
+            .get(name)
+            .and_then(|versions| versions.values().next_back())
+            .map(|worker| ComponentSchema {
+                version: Some(worker.version()),
+                ..worker.schema()
+            })
+    }
+
+    /// Schemas of all registered components, sorted by name
+    pub fn catalog(&self) -> Catalog {
+        let mut names: Vec<&String> = self.workers.keys().collect();
+        names.sort();
+        Catalog {
+            components: names.into_iter().filter_map(|n| self.schema(n)).collect(),
+        }
+    }
+
+    /// Checks every node against the schema of its worker
+    pub fn validate(&self, nodes: &HashMap<i64, Node>) -> Vec<SchemaError> {
+        let mut ids: Vec<&i64> = nodes.keys().collect();
+        ids.sort();
+        ids.into_iter()
+            .flat_map(|id| {
+                let node = &nodes[id];
+                match self.schema(&node.name) {
+                    Some(schema) => schema.validate(node),
+                    None => vec![SchemaError::UnknownComponent {
+                        node_id: node.id,
+                        name: node.name.clone(),
+                    }],
+                }
+            })
+            .collect()
+    }
+
+    /// Reports every connection whose output socket can't feed its input socket.
+    /// Ports that aren't declared by a schema are not checked.
+    pub fn check_types(&self, nodes: &HashMap<i64, Node>, rules: &SocketRules) -> Vec<SchemaError> {
+        let schemas: HashMap<&str, ComponentSchema> = nodes
+            .values()
+            .filter_map(|n| self.schema(&n.name).map(|s| (n.name.as_str(), s)))
+            .collect();
+        let mut errors = vec![];
+        let mut ids: Vec<&i64> = nodes.keys().collect();
+        ids.sort();
+        for id in ids {
+            let node = &nodes[id];
+            let mut outputs: Vec<_> = node.outputs.iter().collect();
+            outputs.sort_by_key(|(name, _)| *name);
+            for (name, output) in outputs {
+                for c in &output.connections {
+                    let output_socket = schemas
+                        .get(node.name.as_str())
+                        .and_then(|s| s.get_output(name))
+                        .map(|p| p.socket);
+                    let input_socket = nodes
+                        .get(&c.node)
+                        .and_then(|n| schemas.get(n.name.as_str()))
+                        .and_then(|s| s.get_input(&c.input))
+                        .map(|p| p.socket);
+                    if let (Some(output), Some(input)) = (output_socket, input_socket) {
+                        if !rules.compatible(output, input) {
+                            errors.push(SchemaError::SocketMismatch {
+                                connection: Connection::new(node.id, name, c.node, &c.input),
+                                output,
+                                input,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        errors
+    }
+
+    fn run(
+        &self,
+        worker: &dyn Worker<TContext>,
+        invocation: &Invocation<'_, TContext>,
+        node: &Node,
+        input: HashMap<String, OutputValue>,
+    ) -> Result<HashMap<String, OutputValue>, WorkerError> {
+        Next::new(worker, &self.layers, invocation)
+            .run(invocation.context(), node, input)
+            .map_err(|source| WorkerError::NodeRunError {
+                node_id: node.id,
+                worker: worker.name().to_string(),
+                source,
             })
-            .ok_or(WorkerError::WorkerNotFound(name.into()))?
     }
 }
 
 pub struct WorkersBuilder<TContext> {
     data: Vec<(String, Box<dyn Worker<TContext>>)>,
+    migrations: HashMap<String, Vec<DataMigration>>,
+    layers: Vec<Box<dyn Layer<TContext>>>,
 }
 
 impl<T> Default for WorkersBuilder<T> {
     fn default() -> Self {
-        Self { data: vec![] }
+        Self {
+            data: vec![],
+            migrations: HashMap::new(),
+            layers: vec![],
+        }
     }
 }
 
@@ -76,7 +335,43 @@ impl<TContext> WorkersBuilder<TContext> {
         self
     }
 
+    /// Upgrades `node.data` of `name` nodes from version `from` to `to`
+    pub fn migration<F>(&mut self, name: &str, from: u32, to: u32, migrate: F) -> &mut Self
+    where
+        F: Fn(&mut HashMap<String, Value>) -> Result<()> + 'static,
+    {
+        self.migrations
+            .entry(name.to_string())
+            .or_default()
+            .push(DataMigration {
+                from,
+                to,
+                migrate: Box::new(migrate),
+            });
+        self
+    }
+
+    /// Wraps every worker call in `layer`. The first layer added is the outermost.
+    pub fn layer<L>(&mut self, layer: L) -> &mut Self
+    where
+        L: Layer<TContext> + 'static,
+    {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
     pub fn build(self) -> Workers<TContext> {
-        Workers(self.data.into_iter().collect::<HashMap<_, _>>())
+        let mut workers: HashMap<String, BTreeMap<u32, Box<dyn Worker<TContext>>>> = HashMap::new();
+        for (name, worker) in self.data {
+            workers
+                .entry(name)
+                .or_default()
+                .insert(worker.version(), worker);
+        }
+        Workers {
+            workers,
+            migrations: self.migrations,
+            layers: self.layers,
+        }
     }
 }